@@ -11,7 +11,7 @@ use discord_rpc_client::models::*;
 fn main() {
     TermLogger::init(LevelFilter::Debug, Config::default()).unwrap();
 
-    let mut drpc = DiscordRPC::new(425407036495495169);
+    let drpc = DiscordRPC::new(425407036495495169);
 
     drpc.start(2);
 
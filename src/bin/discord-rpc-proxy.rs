@@ -0,0 +1,139 @@
+extern crate clap;
+extern crate discord_rpc_client;
+extern crate serde_json;
+extern crate simplelog;
+
+use std::{io, path::PathBuf, process};
+
+use clap::Parser;
+use serde_json::Value;
+use simplelog::*;
+
+use discord_rpc_client::models::OpCode;
+use discord_rpc_client::redact;
+#[cfg(unix)]
+use discord_rpc_client::proxy::{Filter, Proxy};
+use discord_rpc_client::transcript::Direction;
+
+/// Forward Discord IPC traffic and print every frame passing through.
+///
+/// To inspect an application, let it find the proxy instead of Discord,
+/// e.g. with `--listen /tmp/rpc/discord-ipc-0` and `XDG_RUNTIME_DIR=/tmp/rpc` for the application.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Socket to listen on [default: the first unused discord-ipc-N]
+    #[arg(long)]
+    listen: Option<PathBuf>,
+
+    /// Socket of the real Discord [default: the first reachable discord-ipc-N]
+    #[arg(long)]
+    upstream: Option<PathBuf>,
+
+    /// Only show frames going in this direction (sent, received)
+    #[arg(long, value_parser = parse_direction)]
+    direction: Option<Direction>,
+
+    /// Only show frames with this opcode (HANDSHAKE, FRAME, CLOSE, PING, PONG)
+    #[arg(long = "opcode", value_parser = parse_opcode)]
+    opcodes: Vec<OpCode>,
+
+    /// Only show frames with this command, e.g. SET_ACTIVITY
+    #[arg(long = "cmd")]
+    commands: Vec<String>,
+
+    /// Only show frames with this event, e.g. READY
+    #[arg(long = "evt")]
+    events: Vec<String>,
+
+    /// Print payloads on a single line
+    #[arg(long)]
+    compact: bool,
+
+    /// Log what the proxy itself is doing
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Print secrets and tokens instead of masking them
+    #[arg(long)]
+    show_secrets: bool,
+}
+
+fn parse_direction(value: &str) -> Result<Direction, String> {
+    serde_json::from_value(Value::String(value.to_lowercase()))
+        .map_err(|_| format!("unknown direction `{}`", value))
+}
+
+fn parse_opcode(value: &str) -> Result<OpCode, String> {
+    serde_json::from_value(Value::String(value.to_uppercase()))
+        .map_err(|_| format!("unknown opcode `{}`", value))
+}
+
+fn format_payload(payload: &str, compact: bool) -> String {
+    match serde_json::from_str::<Value>(payload) {
+        Ok(ref value) if !compact => serde_json::to_string_pretty(value).unwrap(),
+        Ok(ref value) => value.to_string(),
+        Err(_) => payload.to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn main() {
+    let args = Args::parse();
+    let level = if args.verbose { LevelFilter::Debug } else { LevelFilter::Warn };
+    // Without a terminal, e.g. when the output is piped into a file.
+    if TermLogger::init(level, Config::default()).is_err() {
+        let _ = WriteLogger::init(level, Config::default(), io::stderr());
+    }
+    redact::show_secrets(args.show_secrets);
+
+    let filter = Filter {
+        direction: args.direction,
+        opcodes: args.opcodes,
+        commands: args.commands,
+        events: args.events,
+    };
+    let compact = args.compact;
+
+    let proxy = match Proxy::bind(args.listen, args.upstream) {
+        Ok(proxy) => proxy,
+        Err(why) => {
+            eprintln!("Failed to start proxy: {}", why);
+            process::exit(1);
+        }
+    };
+    eprintln!(
+        "Listening on {}, forwarding to {}",
+        proxy.path().display(),
+        proxy.upstream().display()
+    );
+
+    let proxy = proxy.observe(move |id, direction, message| {
+        if !filter.matches(direction, message) {
+            return;
+        }
+        let arrow = match direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+        println!(
+            "[{}] {} {}\n{}",
+            id,
+            arrow,
+            serde_json::to_value(message.opcode).unwrap().as_str().unwrap(),
+            format_payload(&redact::payload(&message.payload), compact)
+        );
+    });
+
+    if let Err(why) = proxy.run() {
+        eprintln!("Proxy stopped: {}", why);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn main() {
+    Args::parse();
+    eprintln!("The proxy is only supported on Unix systems");
+    process::exit(1);
+}
@@ -0,0 +1,173 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Args;
+use discord_rpc_client::models::{
+    Activity, ActivityAssetsBuilder, ActivityBuilder, ActivityButtonBuilder, ActivityPartyBuilder,
+    ActivityTimestampsBuilder,
+};
+
+/// Everything a presence can show.
+#[derive(Args, Clone, Debug, Default)]
+pub struct ActivityArgs {
+    /// First line below the details
+    #[arg(long)]
+    pub state: Option<String>,
+
+    /// What the player is currently doing
+    #[arg(long)]
+    pub details: Option<String>,
+
+    /// Key of the large image asset
+    #[arg(long)]
+    pub large_image: Option<String>,
+
+    /// Tooltip of the large image
+    #[arg(long)]
+    pub large_text: Option<String>,
+
+    /// Key of the small image asset
+    #[arg(long)]
+    pub small_image: Option<String>,
+
+    /// Tooltip of the small image
+    #[arg(long)]
+    pub small_text: Option<String>,
+
+    /// Start time: `now`, seconds from now like `+60` or a Unix timestamp
+    #[arg(long, value_parser = parse_timestamp)]
+    pub start: Option<u64>,
+
+    /// End time: `now`, seconds from now like `+60` or a Unix timestamp
+    #[arg(long, value_parser = parse_timestamp)]
+    pub end: Option<u64>,
+
+    /// Id of the player's party
+    #[arg(long)]
+    pub party_id: Option<u32>,
+
+    /// Size of the party as `current/max`, e.g. `1/4`
+    #[arg(long, value_parser = parse_party_size)]
+    pub party_size: Option<(u32, u32)>,
+
+    /// Button as `label=url`, can be given twice
+    #[arg(long = "button", value_parser = parse_button)]
+    pub buttons: Vec<(String, String)>,
+
+    /// Whether the activity is an instanced game session
+    #[arg(long)]
+    pub instance: bool,
+}
+
+impl ActivityArgs {
+    pub fn to_activity(&self) -> Result<Activity, String> {
+        let mut activity = ActivityBuilder::default();
+
+        if let Some(ref state) = self.state {
+            activity.state(state.clone());
+        }
+        if let Some(ref details) = self.details {
+            activity.details(details.clone());
+        }
+        if self.instance {
+            activity.instance(true);
+        }
+
+        if self.large_image.is_some()
+            || self.large_text.is_some()
+            || self.small_image.is_some()
+            || self.small_text.is_some()
+        {
+            let mut assets = ActivityAssetsBuilder::default();
+            if let Some(ref large_image) = self.large_image {
+                assets.large_image(large_image.clone());
+            }
+            if let Some(ref large_text) = self.large_text {
+                assets.large_text(large_text.clone());
+            }
+            if let Some(ref small_image) = self.small_image {
+                assets.small_image(small_image.clone());
+            }
+            if let Some(ref small_text) = self.small_text {
+                assets.small_text(small_text.clone());
+            }
+            activity.assets(assets.build().map_err(|why| why.to_string())?);
+        }
+
+        if self.start.is_some() || self.end.is_some() {
+            let mut timestamps = ActivityTimestampsBuilder::default();
+            if let Some(start) = self.start {
+                timestamps.start(start);
+            }
+            if let Some(end) = self.end {
+                timestamps.end(end);
+            }
+            activity.timestamps(timestamps.build().map_err(|why| why.to_string())?);
+        }
+
+        if self.party_id.is_some() || self.party_size.is_some() {
+            let mut party = ActivityPartyBuilder::default();
+            if let Some(id) = self.party_id {
+                party.id(id);
+            }
+            if let Some(size) = self.party_size {
+                party.size(size);
+            }
+            activity.party(party.build().map_err(|why| why.to_string())?);
+        }
+
+        if self.buttons.len() > 2 {
+            return Err("Discord shows at most two buttons".into());
+        }
+        if !self.buttons.is_empty() {
+            let buttons = self
+                .buttons
+                .iter()
+                .map(|(label, url)| {
+                    ActivityButtonBuilder::default()
+                        .label(label.clone())
+                        .url(url.clone())
+                        .build()
+                        .map_err(|why| why.to_string())
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            activity.buttons(buttons);
+        }
+
+        activity.build().map_err(|why| why.to_string())
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+fn parse_timestamp(value: &str) -> Result<u64, String> {
+    if value == "now" {
+        return Ok(unix_now());
+    }
+    if let Some(offset) = value.strip_prefix('+') {
+        let offset: u64 = offset.parse().map_err(|_| format!("invalid offset `{}`", value))?;
+        return Ok(unix_now() + offset);
+    }
+    value.parse().map_err(|_| format!("invalid timestamp `{}`", value))
+}
+
+fn parse_party_size(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid party size `{}`, expected `current/max`", value);
+    let (current, max) = value.split_once('/').ok_or_else(invalid)?;
+    let current = current.trim().parse().map_err(|_| invalid())?;
+    let max = max.trim().parse().map_err(|_| invalid())?;
+    Ok((current, max))
+}
+
+fn parse_button(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((label, url)) if !label.is_empty() && !url.is_empty() => {
+            Ok((label.to_string(), url.to_string()))
+        }
+        _ => Err(format!("invalid button `{}`, expected `label=url`", value)),
+    }
+}
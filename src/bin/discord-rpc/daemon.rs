@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use notify::{self, RecursiveMode, Watcher};
+use serde_json;
+use toml;
+
+use discord_rpc_client::{models::Activity, Client};
+
+/// A presence configuration, either TOML or JSON depending on the file extension.
+///
+/// ```toml
+/// client_id = 425407036495495169
+/// profile = "coding"
+///
+/// [profiles.coding]
+/// details = "Writing Rust"
+///
+/// [profiles.coding.assets]
+/// large_image = "ferris"
+/// ```
+#[derive(Debug, Deserialize)]
+struct Config {
+    client_id: u64,
+    /// Name of the profile to show, may be left out if there is only one.
+    profile: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Activity>,
+}
+
+impl Config {
+    fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path).map_err(|why| why.to_string())?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&content).map_err(|why| why.to_string()),
+            _ => toml::from_str(&content).map_err(|why| why.to_string()),
+        }
+    }
+
+    /// The profile to show, the one passed on the command line takes precedence.
+    fn activity(&self, profile: Option<&str>) -> Result<Option<(String, Activity)>, String> {
+        let name = match profile.or(self.profile.as_deref()) {
+            Some(name) => name.to_string(),
+            None if self.profiles.len() == 1 => self.profiles.keys().next().unwrap().clone(),
+            None if self.profiles.is_empty() => return Ok(None),
+            None => return Err("Several profiles but none is selected".into()),
+        };
+        match self.profiles.get(&name) {
+            Some(activity) => Ok(Some((name, activity.clone()))),
+            None => Err(format!("Unknown profile `{}`", name)),
+        }
+    }
+}
+
+fn apply(client: &Client, activity: &Option<(String, Activity)>) {
+    let result = match *activity {
+        Some((ref name, ref activity)) => {
+            println!("Showing profile `{}`", name);
+            client.set_activity(activity.clone())
+        }
+        None => {
+            println!("Clearing presence");
+            client.clear_activity()
+        }
+    };
+    if let Err(why) = result {
+        eprintln!("Failed to update presence: {}", why);
+    }
+}
+
+fn select(config: &Config, profile: Option<&str>) -> Option<(String, Activity)> {
+    config.activity(profile).unwrap_or_else(|why| {
+        eprintln!("{}", why);
+        None
+    })
+}
+
+/// Keep the presence of the selected profile up, until the process is stopped.
+///
+/// The configuration is reloaded whenever the file changes,
+/// and the presence is restored whenever Discord comes back after a restart.
+pub fn run(path: &Path, profile: Option<&str>) -> Result<(), String> {
+    let mut config = Config::load(path)?;
+    let mut wanted = select(&config, profile);
+
+    let client = Client::new(config.client_id);
+    client.start(u32::MAX);
+
+    // Watch the directory, editors often replace the file instead of writing to it.
+    let path = fs::canonicalize(path).map_err(|why| why.to_string())?;
+    let dir = path.parent().map(PathBuf::from).unwrap_or_else(|| PathBuf::from("."));
+    let (sender, changes) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(|why| why.to_string())?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .map_err(|why| why.to_string())?;
+
+    let mut applied = false;
+    loop {
+        match changes.recv_timeout(Duration::from_secs(1)) {
+            Ok(Ok(event)) => {
+                if !event.paths.iter().any(|changed| changed.file_name() == path.file_name()) {
+                    continue;
+                }
+                // Wait for the editor to finish writing, then only reload once.
+                thread::sleep(Duration::from_millis(100));
+                while changes.try_recv().is_ok() {}
+
+                match Config::load(&path) {
+                    Ok(new_config) => {
+                        if new_config.client_id != config.client_id {
+                            // Discord might not be running, the client switches once it is.
+                            if let Err(why) = client.set_client_id(new_config.client_id, Duration::from_secs(5)) {
+                                eprintln!("Not yet connected as {}: {}", new_config.client_id, why);
+                            }
+                        }
+                        wanted = select(&new_config, profile);
+                        config = new_config;
+                        applied = false;
+                    }
+                    Err(why) => eprintln!("Ignoring invalid configuration: {}", why),
+                }
+            }
+            Ok(Err(why)) => eprintln!("Failed to watch configuration: {}", why),
+            Err(mpsc::RecvTimeoutError::Timeout) => (),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err("Stopped watching configuration".into()),
+        }
+
+        // Discord forgets the presence when it restarts, show it again after reconnecting.
+        if !client.is_connected() {
+            applied = false;
+        } else if !applied {
+            apply(&client, &wanted);
+            applied = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_profile() {
+        let config: Config = toml::from_str(
+            r#"
+            client_id = 1
+            profile = "coding"
+
+            [profiles.coding]
+            details = "Writing Rust"
+
+            [profiles.gaming]
+            details = "Playing"
+            buttons = [{ label = "Join", url = "https://example.com" }]
+            "#,
+        )
+        .unwrap();
+
+        let (name, _) = config.activity(None).unwrap().unwrap();
+        assert_eq![name, "coding"];
+        let (name, activity) = config.activity(Some("gaming")).unwrap().unwrap();
+        assert_eq![name, "gaming"];
+        assert_eq![serde_json::to_value(&activity).unwrap()["buttons"][0]["label"], "Join"];
+        assert!(config.activity(Some("sleeping")).is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    process::{Command, ExitStatus},
+    sync::{mpsc::Receiver, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use discord_rpc_client::{models::Activity, RpcClient};
+
+use activity::{unix_now, ActivityArgs};
+
+/// Clears the presence when dropped, no matter how the command ended.
+struct Presence<C: RpcClient> {
+    client: Option<C>,
+}
+
+impl<C: RpcClient> Presence<C> {
+    fn set(&mut self, activity: Activity) {
+        if let Some(ref client) = self.client {
+            if let Err(why) = client.set_activity(activity) {
+                eprintln!("Failed to set presence: {}", why);
+            }
+        }
+    }
+}
+
+impl<C: RpcClient> Drop for Presence<C> {
+    fn drop(&mut self) {
+        if let Some(ref client) = self.client {
+            if let Err(why) = client.clear_activity() {
+                eprintln!("Failed to clear presence: {}", why);
+            }
+        }
+    }
+}
+
+/// Where the command is, as far as the signal handler is concerned.
+enum Child {
+    /// Not spawned yet, signals wait to be forwarded once it is.
+    Starting(Vec<i32>),
+    Running(u32),
+    Finished,
+}
+
+/// Run `command` while showing a presence and return the exit code to exit with.
+pub fn exec<C: RpcClient>(client: Option<C>, args: &ActivityArgs, linger: u64, command: &[String]) -> i32 {
+    let state_given = args.state.is_some();
+    let mut args = args.clone();
+    if args.details.is_none() && args.state.is_none() {
+        args.details = Some(format!("Running {}", command[0]));
+    }
+    if args.start.is_none() {
+        args.start = Some(unix_now());
+    }
+
+    let mut presence = Presence { client };
+    match args.to_activity() {
+        Ok(activity) => presence.set(activity),
+        Err(why) => {
+            eprintln!("{}", why);
+            return 2;
+        }
+    }
+
+    // Handle signals before there is a child, so none of them kills us with the presence still set.
+    let child_state = Arc::new(Mutex::new(Child::Starting(Vec::new())));
+    let interrupted = forward_signals(Arc::clone(&child_state));
+    let mut child = match Command::new(&command[0]).args(&command[1..]).spawn() {
+        Ok(child) => child,
+        Err(why) => {
+            *child_state.lock().unwrap() = Child::Finished;
+            eprintln!("Failed to run {}: {}", command[0], why);
+            return 127;
+        }
+    };
+    {
+        let mut child_state = child_state.lock().unwrap();
+        if let Child::Starting(ref pending) = *child_state {
+            pending.iter().for_each(|&signal| kill(child.id(), signal));
+        }
+        *child_state = Child::Running(child.id());
+    }
+
+    let status = child.wait();
+    *child_state.lock().unwrap() = Child::Finished;
+    let status = match status {
+        Ok(status) => status,
+        Err(why) => {
+            eprintln!("Failed to wait for {}: {}", command[0], why);
+            return 1;
+        }
+    };
+
+    if !state_given {
+        args.state = Some(describe(&status));
+        if let Ok(activity) = args.to_activity() {
+            presence.set(activity);
+        }
+    }
+
+    // Show the result for a moment, unless we are asked to stop.
+    let linger = Duration::from_secs(linger);
+    match interrupted {
+        Some(interrupted) => {
+            let _ = interrupted.recv_timeout(linger);
+        }
+        None => thread::sleep(linger),
+    }
+
+    exit_code(&status)
+}
+
+fn describe(status: &ExitStatus) -> String {
+    match (status.success(), status.code(), signal(status)) {
+        (true, _, _) => "Finished successfully".into(),
+        (false, Some(code), _) => format!("Failed with exit code {}", code),
+        (false, None, Some(signal)) => format!("Killed by signal {}", signal),
+        (false, None, None) => "Failed".into(),
+    }
+}
+
+fn exit_code(status: &ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| signal(status).map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+#[cfg(unix)]
+fn signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+/// Pass signals on to the child while it runs, afterwards they interrupt the linger time.
+///
+/// SIGINT and SIGQUIT are not forwarded, the terminal already sends them to the whole process group.
+/// Either way we must not die from them before the presence got cleared.
+#[cfg(unix)]
+fn forward_signals(child: Arc<Mutex<Child>>) -> Option<Receiver<()>> {
+    use signal_hook::{
+        consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGUSR1, SIGUSR2},
+        iterator::Signals,
+    };
+    use std::sync::mpsc;
+
+    let mut signals = match Signals::new([SIGINT, SIGQUIT, SIGTERM, SIGHUP, SIGUSR1, SIGUSR2]) {
+        Ok(signals) => signals,
+        Err(why) => {
+            eprintln!("Failed to handle signals: {}", why);
+            return None;
+        }
+    };
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match *child.lock().unwrap() {
+                Child::Finished => {
+                    let _ = sender.send(());
+                }
+                _ if signal == SIGINT || signal == SIGQUIT => (),
+                Child::Starting(ref mut pending) => pending.push(signal),
+                Child::Running(pid) => kill(pid, signal),
+            }
+        }
+    });
+
+    Some(receiver)
+}
+
+#[cfg(not(unix))]
+fn forward_signals(_child: Arc<Mutex<Child>>) -> Option<Receiver<()>> {
+    None
+}
+
+#[cfg(unix)]
+fn kill(pid: u32, signal: i32) {
+    unsafe {
+        libc::kill(pid as libc::pid_t, signal);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(_pid: u32, _signal: i32) {}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+    use discord_rpc_client::offline::PresenceRecorder;
+    use serde_json::{self, Value};
+
+    use super::*;
+
+    /// The activities shown over time as JSON, `null` once cleared.
+    fn shown(recorder: &PresenceRecorder) -> Vec<Value> {
+        recorder
+            .history()
+            .iter()
+            .map(|record| serde_json::to_value(&record.activity).unwrap())
+            .collect()
+    }
+
+    fn shell(script: &str) -> Vec<String> {
+        vec!["sh".into(), "-c".into(), script.into()]
+    }
+
+    #[test]
+    fn test_exit_code_and_clear() {
+        let recorder = PresenceRecorder::new();
+        assert_eq![exec(Some(recorder.clone()), &ActivityArgs::default(), 0, &shell("exit 3")), 3];
+
+        let shown = shown(&recorder);
+        assert_eq![shown.len(), 3];
+        assert_eq![shown[0]["details"], "Running sh"];
+        assert_eq![shown[1]["state"], "Failed with exit code 3"];
+        assert_eq![shown[2], Value::Null];
+    }
+
+    #[test]
+    fn test_keep_given_state() {
+        let recorder = PresenceRecorder::new();
+        let args = ActivityArgs {
+            state: Some("Deploying".into()),
+            ..ActivityArgs::default()
+        };
+        assert_eq![exec(Some(recorder.clone()), &args, 0, &shell("exit 0")), 0];
+
+        let shown = shown(&recorder);
+        assert_eq![shown.len(), 2];
+        assert_eq![shown[0]["state"], "Deploying"];
+        assert_eq![shown[1], Value::Null];
+        assert_eq![exec(None::<PresenceRecorder>, &args, 0, &["/nonexistent".to_string()]), 127];
+    }
+}
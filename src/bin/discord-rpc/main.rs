@@ -0,0 +1,485 @@
+extern crate clap;
+extern crate discord_rpc_client;
+#[cfg(unix)]
+extern crate libc;
+#[macro_use]
+extern crate log;
+extern crate notify;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+#[cfg(unix)]
+extern crate signal_hook;
+extern crate simplelog;
+extern crate toml;
+
+mod activity;
+mod daemon;
+mod exec;
+mod relay;
+mod serve;
+mod sidecar;
+
+use std::{
+    io,
+    path::PathBuf,
+    process, thread,
+    time::Duration,
+};
+
+use clap::{Args, Parser, Subcommand};
+use serde_json::Value;
+use simplelog::*;
+
+use discord_rpc_client::{
+    fanout::FanOut,
+    instances::{self, Channel, Selector},
+    models::{Event, SubscriptionArgs},
+    providers::{self, FixedPid},
+    redact,
+    Client, Error,
+};
+
+use activity::ActivityArgs;
+
+/// Manage your Discord Rich Presence from the command line.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    /// Log what the client is doing
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Log secrets and tokens instead of masking them
+    #[arg(long, global = true)]
+    show_secrets: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct ConnectionArgs {
+    /// Id of the Discord application the presence belongs to
+    #[arg(long, env = "DISCORD_CLIENT_ID", global = true)]
+    client_id: Option<u64>,
+
+    /// Seconds to wait for Discord
+    #[arg(long, default_value_t = 5, global = true)]
+    timeout: u64,
+
+    /// Only connect to Discord of this release channel: stable, ptb or canary
+    #[arg(long, global = true)]
+    channel: Option<Channel>,
+
+    /// Only connect to Discord logged in as this user id
+    #[arg(long, global = true)]
+    user_id: Option<u64>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Set the presence and keep it until interrupted
+    Set {
+        #[command(flatten)]
+        activity: Box<ActivityArgs>,
+
+        /// Seconds to keep the presence before exiting [default: until interrupted]
+        #[arg(long)]
+        duration: Option<u64>,
+
+        /// Show the presence on every running Discord, e.g. both stable and Canary
+        #[arg(long)]
+        all_instances: bool,
+    },
+    /// Show a presence while a command runs, e.g. `discord-rpc exec --details Building -- cargo build`
+    Exec {
+        #[command(flatten)]
+        activity: Box<ActivityArgs>,
+
+        /// Seconds to show the exit status after the command finished
+        #[arg(long, default_value_t = 5)]
+        linger: u64,
+
+        /// The command to run
+        #[arg(required = true, last = true)]
+        command: Vec<String>,
+    },
+    /// Clear the presence of a process
+    Clear {
+        /// Process the presence belongs to [default: this one]
+        #[arg(long)]
+        pid: Option<u32>,
+    },
+    /// Print events dispatched by Discord as JSON lines
+    Watch {
+        /// Event to subscribe to, e.g. ACTIVITY_JOIN [default: all activity events]
+        #[arg(long = "event", value_parser = parse_event)]
+        events: Vec<Event>,
+    },
+    /// Show whether Discord is reachable and who is logged in
+    Status {
+        /// Print the READY event as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// List every running Discord with its release channel and user
+    Instances {
+        /// Print the instances as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Keep the presence described by a TOML or JSON file up, reloading it on changes
+    Daemon {
+        /// Configuration with the client id and presence profiles
+        config: PathBuf,
+
+        /// Profile to show instead of the one selected in the configuration
+        #[arg(long)]
+        profile: Option<String>,
+    },
+    /// Serve an HTTP API on localhost to control the presence from other programs
+    Serve {
+        /// Port to listen on
+        #[arg(long, default_value_t = 6480)]
+        port: u16,
+
+        /// Token requests must send as `Authorization: Bearer <token>`
+        #[arg(long, env = "DISCORD_SERVE_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+    },
+    /// Let several applications share the presence, showing the one with the highest priority
+    Broker {
+        /// Socket to listen on [default: discord-ipc-0, start the broker before Discord]
+        #[arg(long)]
+        listen: Option<PathBuf>,
+
+        /// Priority of an application as `client_id=priority`, the default is 0
+        #[arg(long = "priority", value_parser = parse_priority)]
+        priorities: Vec<(u64, i32)>,
+    },
+    /// Look for everything that keeps the presence from working and suggest fixes
+    Doctor {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Carry the Discord socket over TCP into containers, VMs and remote sessions
+    Relay {
+        #[command(subcommand)]
+        command: relay::RelayCommand,
+    },
+    /// Answer JSON commands read line by line from stdin, replies and events go to stdout
+    Sidecar,
+}
+
+fn parse_event(value: &str) -> Result<Event, String> {
+    serde_json::from_value(Value::String(value.to_uppercase()))
+        .map_err(|_| format!("unknown event `{}`", value))
+}
+
+fn parse_priority(value: &str) -> Result<(u64, i32), String> {
+    let invalid = || format!("invalid priority `{}`, expected `client_id=priority`", value);
+    let (client_id, priority) = value.split_once('=').ok_or_else(invalid)?;
+    Ok((
+        client_id.trim().parse().map_err(|_| invalid())?,
+        priority.trim().parse().map_err(|_| invalid())?,
+    ))
+}
+
+fn fail<T: AsRef<str>>(message: T) -> ! {
+    eprintln!("{}", message.as_ref());
+    process::exit(1);
+}
+
+fn client_id(args: &ConnectionArgs) -> u64 {
+    args.client_id
+        .unwrap_or_else(|| fail("A client id is required, use --client-id or DISCORD_CLIENT_ID"))
+}
+
+/// The Discord instance required with `--channel` and `--user-id`, if any.
+fn selector(args: &ConnectionArgs) -> Option<Selector> {
+    if args.channel.is_none() && args.user_id.is_none() {
+        return None;
+    }
+    Some(Selector {
+        channel: args.channel,
+        user_id: args.user_id,
+        required: true,
+        ..Selector::default()
+    })
+}
+
+fn new_client(args: &ConnectionArgs) -> Client {
+    let client = Client::new(client_id(args));
+    if let Err(why) = client.select_instance(selector(args)) {
+        fail(why.to_string());
+    }
+    client
+}
+
+/// Start a client and wait until it is connected, failing if Discord didn't show up in time.
+fn connect(args: &ConnectionArgs) -> Result<Client, Error> {
+    let client = new_client(args);
+    client.try_start(1, Duration::from_secs(args.timeout))?;
+    Ok(client)
+}
+
+fn connect_or_fail(args: &ConnectionArgs) -> Client {
+    connect(args).unwrap_or_else(|why| match why {
+        Error::HandshakeRejected(_) => fail(why.to_string()),
+        _ => fail(format!("Discord is not reachable, is it running? ({})", why)),
+    })
+}
+
+fn set(args: &ConnectionArgs, activity: &ActivityArgs, duration: Option<u64>, all_instances: bool) {
+    let activity = activity.to_activity().unwrap_or_else(|why| fail(why));
+
+    if all_instances {
+        if selector(args).is_some() {
+            fail("--all-instances can't be combined with --channel or --user-id");
+        }
+        let fan_out = FanOut::connect(client_id(args), Duration::from_secs(args.timeout))
+            .unwrap_or_else(|why| fail(format!("Discord is not reachable, is it running? ({})", why)));
+        let results = fan_out.set_activity(activity);
+        for (instance, result) in fan_out.instances().into_iter().zip(results) {
+            if let Err(why) = result {
+                eprintln!("Failed to set presence on {}: {}", instance.path.display(), why);
+            }
+        }
+        keep_presence(duration);
+    } else {
+        let client = connect_or_fail(args);
+        if let Err(why) = client.set_activity(activity) {
+            fail(format!("Failed to set presence: {}", why));
+        }
+        keep_presence(duration);
+    }
+}
+
+/// Discord removes the presence as soon as the connection is closed.
+fn keep_presence(duration: Option<u64>) {
+    match duration {
+        Some(seconds) => thread::sleep(Duration::from_secs(seconds)),
+        None => {
+            eprintln!("Presence set, interrupt to clear it");
+            loop {
+                thread::park();
+            }
+        }
+    }
+}
+
+fn clear(args: &ConnectionArgs, pid: Option<u32>) {
+    if let Some(pid) = pid {
+        providers::set_pid_provider(FixedPid(pid));
+    }
+    let client = connect_or_fail(args);
+
+    if let Err(why) = client.clear_activity() {
+        fail(format!("Failed to clear presence: {}", why));
+    }
+}
+
+fn watch(args: &ConnectionArgs, mut events: Vec<Event>) {
+    if events.is_empty() {
+        events = vec![
+            Event::ActivityJoin,
+            Event::ActivitySpectate,
+            Event::ActivityJoinRequest,
+        ];
+    }
+    let client = connect_or_fail(args);
+
+    for event in events {
+        if let Err(why) = client.subscribe(event, SubscriptionArgs::new()) {
+            fail(format!("Failed to subscribe to {:?}: {}", event, why));
+        }
+    }
+
+    loop {
+        match client.recv_event(Duration::from_secs(1)) {
+            Ok(event) => println!("{}", serde_json::to_string(&event).unwrap()),
+            Err(Error::Timeout(_)) => continue,
+            Err(why) => fail(format!("Failed to receive event: {}", why)),
+        }
+    }
+}
+
+fn status(args: &ConnectionArgs, json: bool) {
+    let client = match connect(args) {
+        Ok(client) => client,
+        Err(why) => {
+            if json {
+                println!("null");
+            } else {
+                println!("Not connected: {}", why);
+            }
+            process::exit(1);
+        }
+    };
+    let ready = client.ready().unwrap_or_default();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&ready).unwrap());
+        return;
+    }
+
+    println!("Connected");
+    if let Some(user) = ready.user {
+        println!(
+            "User:         {}#{} ({})",
+            user.username.unwrap_or_default(),
+            user.discriminator.unwrap_or_default(),
+            user.id.unwrap_or_default()
+        );
+    }
+    if let Some(config) = ready.config {
+        println!("Environment:  {}", config.environment.unwrap_or_default());
+        println!("API endpoint: {}", config.api_endpoint.unwrap_or_default());
+    }
+}
+
+fn list_instances(args: &ConnectionArgs, json: bool) {
+    let selector = selector(args).unwrap_or_default();
+    let found: Vec<_> = instances::list(client_id(args), Duration::from_secs(args.timeout))
+        .into_iter()
+        .filter(|instance| selector.matches(instance))
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&found).unwrap());
+        return;
+    }
+    if found.is_empty() {
+        println!("No Discord instance found");
+    }
+
+    for instance in found {
+        let channel = instance.channel.map_or_else(|| "unknown".to_string(), |channel| channel.to_string());
+        let user = instance.ready.user.clone().unwrap_or_default();
+        let config = instance.ready.config.clone().unwrap_or_default();
+        println!("{}", instance.path.display());
+        println!("  Channel:      {}", channel);
+        println!(
+            "  User:         {}#{} ({})",
+            user.username.unwrap_or_default(),
+            user.discriminator.unwrap_or_default(),
+            user.id.unwrap_or_default()
+        );
+        println!("  Environment:  {}", config.environment.unwrap_or_default());
+        println!("  API endpoint: {}", config.api_endpoint.unwrap_or_default());
+    }
+}
+
+#[cfg(unix)]
+fn doctor(args: &ConnectionArgs, json: bool) {
+    let report = discord_rpc_client::doctor::diagnose(args.client_id);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+    } else {
+        print!("{}", report);
+    }
+    if !report.healthy() {
+        process::exit(1);
+    }
+}
+
+#[cfg(not(unix))]
+fn doctor(_args: &ConnectionArgs, _json: bool) {
+    fail("The doctor is only supported on Unix systems");
+}
+
+#[cfg(unix)]
+fn broker(args: &ConnectionArgs, listen: Option<PathBuf>, priorities: Vec<(u64, i32)>) {
+    use discord_rpc_client::broker::Broker;
+
+    let mut broker = Broker::bind(listen)
+        .unwrap_or_else(|why| fail(format!("Failed to start broker: {}", why)));
+    for (client_id, priority) in priorities {
+        broker = broker.priority(client_id, priority);
+    }
+    eprintln!("Listening on {}", broker.path().display());
+
+    // The upstream client looks for Discord everywhere but at the broker itself.
+    let client = Client::new(client_id(args));
+    let selector = selector(args).unwrap_or_else(Selector::prefer).exclude(broker.path());
+    if let Err(why) = client.select_instance(Some(selector)) {
+        fail(why.to_string());
+    }
+    client.start(u32::MAX);
+    if let Err(why) = broker.run(client) {
+        fail(format!("Broker stopped: {}", why));
+    }
+}
+
+#[cfg(not(unix))]
+fn broker(_args: &ConnectionArgs, _listen: Option<PathBuf>, _priorities: Vec<(u64, i32)>) {
+    fail("The broker is only supported on Unix systems");
+}
+
+/// Log to the terminal, or to stderr where there is none, e.g. in CI or under systemd.
+///
+/// The terminal logger writes to stdout, which is reserved for replies and events in `sidecar`.
+fn init_logger(level: LevelFilter, command: &Command) {
+    let on_terminal = match *command {
+        Command::Sidecar => false,
+        _ => TermLogger::init(level, Config::default()).is_ok(),
+    };
+    if !on_terminal {
+        let _ = WriteLogger::init(level, Config::default(), io::stderr());
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let level = if cli.verbose { LevelFilter::Debug } else { LevelFilter::Off };
+    init_logger(level, &cli.command);
+    redact::show_secrets(cli.show_secrets);
+
+    match cli.command {
+        Command::Set {
+            activity,
+            duration,
+            all_instances,
+        } => set(&cli.connection, &activity, duration, all_instances),
+        Command::Exec {
+            activity,
+            linger,
+            command,
+        } => {
+            let client = connect(&cli.connection)
+                .map_err(|why| eprintln!("Discord is not reachable, running without presence ({})", why))
+                .ok();
+            process::exit(exec::exec(client, &activity, linger, &command));
+        }
+        Command::Clear { pid } => clear(&cli.connection, pid),
+        Command::Watch { events } => watch(&cli.connection, events),
+        Command::Status { json } => status(&cli.connection, json),
+        Command::Instances { json } => list_instances(&cli.connection, json),
+        Command::Daemon { config, profile } => {
+            if let Err(why) = daemon::run(&config, profile.as_deref()) {
+                fail(why);
+            }
+        }
+        Command::Serve { port, token } => {
+            let client = new_client(&cli.connection);
+            client.start(u32::MAX);
+            if let Err(why) = serve::run(client, port, token) {
+                fail(why);
+            }
+        }
+        Command::Broker { listen, priorities } => broker(&cli.connection, listen, priorities),
+        Command::Doctor { json } => doctor(&cli.connection, json),
+        Command::Relay { command } => {
+            if let Err(why) = relay::run(command) {
+                fail(why);
+            }
+        }
+        Command::Sidecar => sidecar::run(new_client(&cli.connection)),
+    }
+}
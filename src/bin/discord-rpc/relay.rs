@@ -0,0 +1,125 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+#[cfg(unix)]
+use discord_rpc_client::{models::Message, redact, transcript::Direction};
+
+#[derive(Subcommand)]
+pub enum RelayCommand {
+    /// Run next to Discord and accept relayed connections over TCP
+    Host {
+        /// Address to listen on, e.g. the address of the container bridge
+        #[arg(long, default_value = "127.0.0.1:6481")]
+        listen: String,
+
+        /// Socket of the real Discord [default: the first reachable discord-ipc-N]
+        #[arg(long)]
+        upstream: Option<PathBuf>,
+
+        #[command(flatten)]
+        options: RelayArgs,
+    },
+    /// Run where the applications are and expose a Discord socket for them
+    Guest {
+        /// Address of the host relay
+        #[arg(long)]
+        host: String,
+
+        /// Socket to listen on [default: the first unused discord-ipc-N]
+        #[arg(long)]
+        listen: Option<PathBuf>,
+
+        #[command(flatten)]
+        options: RelayArgs,
+    },
+}
+
+#[derive(Args)]
+pub struct RelayArgs {
+    /// Shared secret both sides must agree on
+    #[arg(long, env = "DISCORD_RELAY_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Print every frame passing through
+    #[arg(long)]
+    log: bool,
+
+    /// Only print frames with this command, e.g. SET_ACTIVITY
+    #[arg(long = "cmd")]
+    commands: Vec<String>,
+
+    /// Only print frames with this event, e.g. READY
+    #[arg(long = "evt")]
+    events: Vec<String>,
+}
+
+#[cfg(unix)]
+impl RelayArgs {
+    fn observer(&self) -> impl Fn(usize, Direction, &Message) + Send + Sync + 'static {
+        use discord_rpc_client::proxy::Filter;
+
+        let filter = Filter {
+            commands: self.commands.clone(),
+            events: self.events.clone(),
+            ..Filter::default()
+        };
+        let log = self.log;
+        move |id, direction, message| {
+            if !log || !filter.matches(direction, message) {
+                return;
+            }
+            let arrow = match direction {
+                Direction::Sent => "->",
+                Direction::Received => "<-",
+            };
+            let opcode = serde_json::to_value(message.opcode).unwrap();
+            let payload = redact::payload(&message.payload);
+            println!("[{}] {} {} {}", id, arrow, opcode.as_str().unwrap(), payload);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn run(command: RelayCommand) -> Result<(), String> {
+    use discord_rpc_client::relay::{GuestRelay, HostRelay};
+
+    match command {
+        RelayCommand::Host {
+            listen,
+            upstream,
+            options,
+        } => {
+            let relay = HostRelay::bind(listen.as_str(), upstream)
+                .map_err(|why| why.to_string())?
+                .token(options.token.clone())
+                .observe(options.observer());
+            eprintln!(
+                "Listening on {}, forwarding to {}",
+                relay.local_addr().map_err(|why| why.to_string())?,
+                relay.upstream().display()
+            );
+            if options.token.is_none() {
+                eprintln!("No token given, anyone who can reach the port can use Discord");
+            }
+            relay.run().map_err(|why| why.to_string())
+        }
+        RelayCommand::Guest {
+            host,
+            listen,
+            options,
+        } => {
+            let relay = GuestRelay::bind(listen, host)
+                .map_err(|why| why.to_string())?
+                .token(options.token.clone())
+                .observe(options.observer());
+            eprintln!("Listening on {}, relaying to {}", relay.path().display(), relay.host());
+            relay.run().map_err(|why| why.to_string())
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn run(_command: RelayCommand) -> Result<(), String> {
+    Err("The relay is only supported on Unix systems".into())
+}
@@ -0,0 +1,363 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde_json::{self, Value};
+
+use discord_rpc_client::{
+    models::{Activity, Event, SubscriptionArgs},
+    redact, Client, Error,
+};
+
+/// Requests larger than this are refused, an activity is only a few hundred bytes.
+const MAX_BODY: usize = 64 * 1024;
+
+type Listeners = Arc<Mutex<Vec<mpsc::Sender<String>>>>;
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    /// Names in lowercase.
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.0 == name)
+            .map(|header| header.1.as_str())
+    }
+}
+
+struct Response {
+    status: &'static str,
+    body: Value,
+}
+
+impl Response {
+    fn new(status: &'static str, body: Value) -> Self {
+        Self { status, body }
+    }
+
+    fn error<T: ToString>(status: &'static str, message: T) -> Self {
+        Self::new(status, json!({ "error": message.to_string() }))
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let body = self.body.to_string();
+        write!(
+            writer,
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            body.len(),
+            body
+        )?;
+        writer.flush()
+    }
+}
+
+/// Read a request, `None` if the connection was closed before sending one.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(invalid("Malformed request line")),
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("Unexpected end of headers"));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    let length = match headers.iter().find(|header| header.0 == "content-length") {
+        Some(header) => header.1.parse().map_err(|_| invalid("Invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY {
+        return Err(invalid("Request body too large"));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    }))
+}
+
+/// Keeps web pages away from the API.
+///
+/// Browsers let any page send requests to localhost, and with DNS rebinding read the replies too,
+/// so a request must be addressed to localhost and may only come from a page served there.
+struct Guard {
+    port: u16,
+    token: Option<String>,
+}
+
+impl Guard {
+    /// Whether `authority` of a `Host` or `Origin` header is this server on localhost.
+    fn is_local(&self, authority: &str) -> bool {
+        let authority = authority.to_ascii_lowercase();
+        ["localhost", "127.0.0.1", "[::1]"].iter().any(|host| {
+            authority == format!("{}:{}", host, self.port) || (self.port == 80 && authority == *host)
+        })
+    }
+
+    /// The response refusing `request`, `None` if it may be answered.
+    fn check(&self, request: &Request) -> Option<Response> {
+        if !request.header("host").is_some_and(|host| self.is_local(host)) {
+            return Some(Response::error("403 Forbidden", "Host must be localhost"));
+        }
+        if let Some(origin) = request.header("origin") {
+            if !origin.strip_prefix("http://").is_some_and(|origin| self.is_local(origin)) {
+                return Some(Response::error("403 Forbidden", "Cross-origin requests are not allowed"));
+            }
+        }
+        if let Some(ref token) = self.token {
+            let given = request
+                .header("authorization")
+                .and_then(|value| value.strip_prefix("Bearer "));
+            if !given.is_some_and(|given| redact::same_token(token, given)) {
+                return Some(Response::error("401 Unauthorized", "Missing or wrong token"));
+            }
+        }
+        None
+    }
+}
+
+fn set_activity(client: &Client, body: &[u8]) -> Response {
+    let activity: Activity = match serde_json::from_slice(body) {
+        Ok(activity) => activity,
+        Err(why) => return Response::error("400 Bad Request", why),
+    };
+    if !client.is_connected() {
+        return Response::error("503 Service Unavailable", "Discord is not connected");
+    }
+    match client.set_activity(activity) {
+        Ok(payload) => Response::new("200 OK", json!(payload.data)),
+        Err(why) => Response::error("502 Bad Gateway", why),
+    }
+}
+
+fn clear_activity(client: &Client) -> Response {
+    if !client.is_connected() {
+        return Response::error("503 Service Unavailable", "Discord is not connected");
+    }
+    match client.clear_activity() {
+        Ok(_) => Response::new("200 OK", Value::Null),
+        Err(why) => Response::error("502 Bad Gateway", why),
+    }
+}
+
+fn status(client: &Client) -> Response {
+    Response::new(
+        "200 OK",
+        json!({
+            "connected": client.is_connected(),
+            "ready": client.ready(),
+        }),
+    )
+}
+
+/// Stream events to the client until it goes away.
+fn events(mut stream: TcpStream, listeners: &Listeners) -> io::Result<()> {
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+    )?;
+    stream.flush()?;
+
+    let (sender, receiver) = mpsc::channel();
+    listeners.lock().unwrap().push(sender);
+
+    loop {
+        match receiver.recv_timeout(Duration::from_secs(15)) {
+            Ok(event) => stream.write_all(event.as_bytes())?,
+            // Comments keep proxies from closing the stream and tell us when the client left.
+            Err(mpsc::RecvTimeoutError::Timeout) => stream.write_all(b":\n\n")?,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        stream.flush()?;
+    }
+}
+
+fn handle(stream: TcpStream, client: &Client, listeners: &Listeners, guard: &Guard) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = match read_request(&mut reader) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(()),
+        Err(why) => return Response::error("400 Bad Request", why).write_to(&mut &stream),
+    };
+    debug!("{} {}", request.method, request.path);
+    if let Some(refused) = guard.check(&request) {
+        return refused.write_to(&mut &stream);
+    }
+
+    let response = match (request.method.as_str(), request.path.as_str()) {
+        ("PUT", "/activity") => set_activity(client, &request.body),
+        ("DELETE", "/activity") => clear_activity(client),
+        ("GET", "/status") => status(client),
+        ("GET", "/events") => return events(stream, listeners),
+        (_, "/activity") | (_, "/status") | (_, "/events") => {
+            Response::error("405 Method Not Allowed", "Method not allowed")
+        }
+        _ => Response::error("404 Not Found", "Not found"),
+    };
+    response.write_to(&mut &stream)
+}
+
+/// Pass events from Discord on to every connected event stream.
+///
+/// Discord forgets subscriptions when it restarts, so they are renewed on every connect.
+fn pump_events(client: Client, listeners: Listeners) {
+    let mut subscribed = false;
+    loop {
+        if !client.is_connected() {
+            subscribed = false;
+        } else if !subscribed {
+            for event in &[Event::ActivityJoin, Event::ActivitySpectate, Event::ActivityJoinRequest] {
+                if let Err(why) = client.subscribe(*event, SubscriptionArgs::new()) {
+                    error!("Failed to subscribe to {:?}: {}", event, why);
+                }
+            }
+            subscribed = true;
+        }
+
+        let payload = match client.recv_event(Duration::from_secs(1)) {
+            Ok(payload) => payload,
+            Err(Error::Timeout(_)) => continue,
+            Err(why) => {
+                error!("Failed to receive event: {}", why);
+                continue;
+            }
+        };
+        let name = payload
+            .evt
+            .and_then(|evt| serde_json::to_value(evt).ok())
+            .and_then(|evt| evt.as_str().map(String::from))
+            .unwrap_or_else(|| "message".into());
+        let event = format!("event: {}\ndata: {}\n\n", name, serde_json::to_string(&payload).unwrap());
+
+        listeners
+            .lock()
+            .unwrap()
+            .retain(|listener| listener.send(event.clone()).is_ok());
+    }
+}
+
+/// Serve the HTTP API on localhost until the process is stopped,
+/// requiring `Authorization: Bearer <token>` if there is a `token`.
+pub fn run(client: Client, port: u16, token: Option<String>) -> Result<(), String> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).map_err(|why| why.to_string())?;
+    eprintln!("Listening on http://{}", listener.local_addr().map_err(|why| why.to_string())?);
+    serve(listener, client, token).map_err(|why| why.to_string())
+}
+
+fn serve(listener: TcpListener, client: Client, token: Option<String>) -> io::Result<()> {
+    let guard = Arc::new(Guard {
+        port: listener.local_addr()?.port(),
+        token,
+    });
+    let listeners: Listeners = Arc::new(Mutex::new(Vec::new()));
+    {
+        let client = client.clone();
+        let listeners = Arc::clone(&listeners);
+        thread::spawn(move || pump_events(client, listeners));
+    }
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(why) => {
+                error!("Failed to accept connection: {}", why);
+                continue;
+            }
+        };
+        let client = client.clone();
+        let listeners = Arc::clone(&listeners);
+        let guard = Arc::clone(&guard);
+        thread::spawn(move || {
+            if let Err(why) = handle(stream, &client, &listeners, &guard) {
+                debug!("Connection closed: {}", why);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    /// Send a `GET /status` with `headers` and return the status line of the response.
+    fn get_status(port: u16, headers: &str) -> String {
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        write!(stream, "GET /status HTTP/1.1\r\n{}\r\n", headers).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn test_read_request() {
+        let raw = "PUT /activity HTTP/1.1\r\nHost: localhost\r\ncontent-length: 17\r\n\r\n{\"state\":\"Idle\"}\n";
+        let request = read_request(&mut raw.as_bytes()).unwrap().unwrap();
+        assert_eq![request.method, "PUT"];
+        assert_eq![request.path, "/activity"];
+
+        let activity: Activity = serde_json::from_slice(&request.body).unwrap();
+        assert_eq![serde_json::to_value(&activity).unwrap()["state"], "Idle"];
+        assert!(read_request(&mut "".as_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_refuse_foreign_requests() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || serve(listener, Client::new(1), Some("secret".into())));
+
+        let local = format!("Host: localhost:{}\r\n", port);
+        let authorized = format!("{}Authorization: Bearer secret\r\n", local);
+        assert_eq![get_status(port, &authorized), "HTTP/1.1 200 OK"];
+        assert_eq![
+            get_status(port, &format!("Host: 127.0.0.1:{}\r\nOrigin: http://127.0.0.1:{}\r\nAuthorization: Bearer secret\r\n", port, port)),
+            "HTTP/1.1 200 OK"
+        ];
+
+        // A rebound DNS name, a page elsewhere, a missing or wrong token.
+        let rebound = format!("Host: attacker.example:{}\r\nAuthorization: Bearer secret\r\n", port);
+        assert_eq![get_status(port, &rebound), "HTTP/1.1 403 Forbidden"];
+        assert_eq![get_status(port, "Host: localhost:1\r\nAuthorization: Bearer secret\r\n"), "HTTP/1.1 403 Forbidden"];
+        assert_eq![
+            get_status(port, &format!("{}Origin: http://attacker.example\r\n", authorized)),
+            "HTTP/1.1 403 Forbidden"
+        ];
+        assert_eq![get_status(port, &local), "HTTP/1.1 401 Unauthorized"];
+        assert_eq![get_status(port, &format!("{}Authorization: Bearer guess\r\n", local)), "HTTP/1.1 401 Unauthorized"];
+    }
+}
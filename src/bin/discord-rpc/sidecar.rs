@@ -0,0 +1,180 @@
+use std::{
+    io::{self, BufRead},
+    thread,
+    time::Duration,
+};
+
+use serde::Serialize;
+use serde_json::{self, Value};
+
+use discord_rpc_client::{
+    models::{payload::Payload, Activity, Command, Event, SubscriptionArgs},
+    Client, Error,
+};
+
+/// A command read from stdin, shaped like the payloads Discord itself expects.
+///
+/// ```json
+/// {"id": 1, "cmd": "SET_ACTIVITY", "args": {"activity": {"state": "In menus"}}}
+/// {"id": 2, "cmd": "SET_ACTIVITY", "args": {}}
+/// {"id": 3, "cmd": "SUBSCRIBE", "evt": "ACTIVITY_JOIN_REQUEST"}
+/// {"id": 4, "cmd": "SEND_ACTIVITY_JOIN_INVITE", "args": {"user_id": "53908232506183680"}}
+/// ```
+///
+/// The `id` can be any JSON value and is copied to the reply.
+#[derive(Debug, Deserialize)]
+struct Request {
+    cmd: Command,
+    #[serde(default)]
+    args: Value,
+    evt: Option<Event>,
+}
+
+/// The answer to a request, carrying the id it was sent with.
+#[derive(Debug, Serialize)]
+struct Reply {
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmd: Option<Command>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Reply {
+    fn new(id: Value, cmd: Option<Command>, result: Result<Value, String>) -> Self {
+        let (data, error) = match result {
+            Ok(data) => (Some(data), None),
+            Err(why) => (None, Some(why)),
+        };
+        Self { id, cmd, data, error }
+    }
+}
+
+/// Discord sends user ids as strings, but numbers are accepted as well.
+fn user_id(args: &Value) -> Result<u64, String> {
+    match args.get("user_id") {
+        Some(Value::String(id)) => id.parse().map_err(|_| format!("Invalid user id `{}`", id)),
+        Some(Value::Number(id)) => id.as_u64().ok_or_else(|| format!("Invalid user id `{}`", id)),
+        _ => Err("Missing `user_id` argument".into()),
+    }
+}
+
+fn data(payload: Result<Payload<impl Serialize>, Error>) -> Result<Value, String> {
+    let payload = payload.map_err(|why| why.to_string())?;
+    Ok(serde_json::to_value(payload.data).unwrap())
+}
+
+fn execute(client: &Client, request: Request) -> Result<Value, String> {
+    if !client.is_connected() {
+        return Err("Discord is not connected".into());
+    }
+    let event = || request.evt.ok_or_else(|| "Missing `evt`".to_string());
+    let subscription = || -> Result<SubscriptionArgs, String> {
+        match request.args {
+            Value::Null => Ok(SubscriptionArgs::new()),
+            ref args => serde_json::from_value(args.clone()).map_err(|why| why.to_string()),
+        }
+    };
+
+    match request.cmd {
+        Command::SetActivity => match request.args.get("activity") {
+            None | Some(Value::Null) => data(client.clear_activity()),
+            Some(activity) => {
+                let activity: Activity =
+                    serde_json::from_value(activity.clone()).map_err(|why| why.to_string())?;
+                data(client.set_activity(activity))
+            }
+        },
+        Command::Subscribe => data(client.subscribe(event()?, subscription()?)),
+        Command::Unsubscribe => data(client.unsubscribe(event()?, subscription()?)),
+        Command::SendActivityJoinInvite => {
+            data(client.send_activity_join_invite(user_id(&request.args)?))
+        }
+        Command::CloseActivityRequest => data(client.close_activity_request(user_id(&request.args)?)),
+        Command::Dispatch | Command::Authorize => Err(format!("Unsupported command {:?}", request.cmd)),
+    }
+}
+
+fn handle(client: &Client, line: &str) -> Reply {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(why) => return Reply::new(Value::Null, None, Err(why.to_string())),
+    };
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    match serde_json::from_value::<Request>(value) {
+        Ok(request) => {
+            let cmd = request.cmd;
+            Reply::new(id, Some(cmd), execute(client, request))
+        }
+        Err(why) => Reply::new(id, None, Err(why.to_string())),
+    }
+}
+
+/// Print DISPATCH events, and a READY event whenever the client (re)connects.
+///
+/// Discord forgets subscriptions when it restarts, they need to be sent again after READY.
+fn forward_events(client: Client) {
+    let mut connected = false;
+    loop {
+        if client.is_connected() != connected {
+            connected = !connected;
+            if let (true, Some(ready)) = (connected, client.ready()) {
+                let event = json!({ "cmd": Command::Dispatch, "evt": Event::Ready, "data": ready });
+                println!("{}", event);
+            }
+        }
+
+        match client.recv_event(Duration::from_millis(200)) {
+            Ok(event) => println!("{}", serde_json::to_string(&event).unwrap()),
+            Err(Error::Timeout(_)) => (),
+            Err(why) => error!("Failed to receive event: {}", why),
+        }
+    }
+}
+
+/// Answer commands from stdin until it is closed.
+pub fn run(client: Client) {
+    client.start(u32::MAX);
+    let events = client.clone();
+    thread::spawn(move || forward_events(events));
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(why) => {
+                error!("Failed to read stdin: {}", why);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        println!("{}", serde_json::to_string(&handle(&client, &line)).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_requests() {
+        let client = Client::new(0);
+
+        let reply = handle(&client, r#"{"id": 7, "cmd": "SET_ACTIVITY", "args": {}}"#);
+        assert_eq![reply.id, 7];
+        assert_eq![reply.cmd, Some(Command::SetActivity)];
+        assert_eq![reply.error.unwrap(), "Discord is not connected"];
+
+        let reply = handle(&client, r#"{"id": "a", "cmd": "FLY"}"#);
+        assert_eq![reply.id, "a"];
+        assert!(reply.cmd.is_none() && reply.error.is_some());
+
+        assert!(handle(&client, "{").error.is_some());
+        assert_eq![user_id(&json!({ "user_id": "42" })), Ok(42)];
+        assert_eq![user_id(&json!({ "user_id": 42 })), Ok(42)];
+    }
+}
@@ -0,0 +1,489 @@
+//! A presence multiplexer for several local applications.
+//!
+//! The broker listens on its own `discord-ipc-N` socket and speaks just enough of the
+//! IPC protocol for clients to set and clear their activity. Clients connect to the first
+//! socket that answers, so the broker has to listen before Discord does. Of all activities the one
+//! with the highest priority wins, ties go to the most recently updated one, and only
+//! the winner is forwarded upstream through a single client.
+#![cfg(all(unix, feature = "rich_presence"))]
+
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs,
+    io::{self, ErrorKind},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use serde_json::{self, Value};
+
+use client::RpcClient;
+use connection::{self, Connection, SocketConnection};
+use error::{Error, Result};
+use models::{payload::Payload, Activity, Command, ErrorEvent, Event, Message, OpCode, ReadyEvent};
+
+/// How long the upstream connection is watched at once, bounds how long the watcher outlives the broker.
+const WATCH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a connected application currently wants to show.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Claim {
+    pub client_id: u64,
+    pub priority: i32,
+    pub activity: Activity,
+    /// Increases with every update, the larger the more recent.
+    pub sequence: u64,
+}
+
+#[derive(Default)]
+struct State {
+    claims: HashMap<usize, Claim>,
+    priorities: HashMap<u64, i32>,
+    sequence: u64,
+    ready: Option<ReadyEvent>,
+}
+
+impl State {
+    fn winner(&self) -> Option<&Claim> {
+        self.claims
+            .values()
+            .max_by_key(|claim| (claim.priority, claim.sequence))
+    }
+}
+
+/// What the forwarder reacts to.
+enum Update {
+    /// The activity to show upstream changed, `None` clears it.
+    Winner(Option<Box<Activity>>),
+    /// The upstream client connected or lost its connection.
+    Connection,
+}
+
+pub struct Broker {
+    listener: UnixListener,
+    path: PathBuf,
+    state: Arc<Mutex<State>>,
+    updates: Mutex<Option<mpsc::Sender<Update>>>,
+}
+
+impl Broker {
+    /// Listen on `path` for applications wanting to show a presence.
+    ///
+    /// Without a `path` the first unused `discord-ipc-N` socket is taken, as long as
+    /// no socket before it is served, since clients would reach that one instead.
+    /// Fails with `ErrorKind::AddrInUse` if Discord already listens there, the broker has
+    /// to be started first then. The upstream client has to skip [`Broker::path`].
+    pub fn bind(path: Option<PathBuf>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => default_path()?,
+        };
+        let listener = connection::listen(&path)?;
+
+        Ok(Self {
+            listener,
+            path,
+            state: Arc::new(Mutex::new(State::default())),
+            updates: Mutex::new(None),
+        })
+    }
+
+    /// Rank the activities of an application, the default priority is `0`.
+    ///
+    /// Applications may also send a `priority` next to the activity in `SET_ACTIVITY`.
+    pub fn priority(self, client_id: u64, priority: i32) -> Self {
+        self.state.lock().priorities.insert(client_id, priority);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The claim currently shown upstream.
+    pub fn winner(&self) -> Option<Claim> {
+        self.state.lock().winner().cloned()
+    }
+
+    /// Accept applications and forward the winning activity to `upstream` until the listener fails.
+    pub fn run<C>(&self, upstream: C) -> Result<()>
+        where
+            C: RpcClient + Send + Sync + 'static,
+    {
+        let upstream = Arc::new(upstream);
+        let (sender, receiver) = mpsc::channel();
+        *self.updates.lock() = Some(sender.clone());
+        {
+            let upstream = Arc::clone(&upstream);
+            thread::spawn(move || watch(&*upstream, &sender));
+        }
+        let state = Arc::clone(&self.state);
+        thread::spawn(move || forward(&*upstream, &receiver, &state));
+
+        for (n, stream) in self.listener.incoming().enumerate() {
+            let stream = stream?;
+            let id = n + 1;
+            let state = Arc::clone(&self.state);
+            let updates = self.updates.lock().clone().unwrap();
+            thread::spawn(move || {
+                if let Err(why) = serve(id, stream, &state, &updates) {
+                    debug!("[{}] Connection failed: {}", id, why);
+                }
+                release(id, &state, &updates);
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Broker {
+    fn drop(&mut self) {
+        if let Err(why) = fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {}", self.path.display(), why);
+        }
+    }
+}
+
+/// The first `discord-ipc-N` socket, unless it is served already.
+fn default_path() -> Result<PathBuf> {
+    let path = SocketConnection::socket_path(0);
+    if UnixStream::connect(&path).is_ok() {
+        let message = format!(
+            "{} is served already, start the broker before Discord or choose a socket",
+            path.display()
+        );
+        return Err(io::Error::new(ErrorKind::AddrInUse, message).into());
+    }
+    Ok(path)
+}
+
+/// Tell the forwarder whenever `upstream` connects or loses its connection.
+fn watch<C: RpcClient>(upstream: &C, updates: &mpsc::Sender<Update>) {
+    let mut connected = upstream.is_connected();
+    loop {
+        let now = upstream.wait_for_connection_change(connected, WATCH_TIMEOUT);
+        if now != connected && updates.send(Update::Connection).is_err() {
+            return;
+        }
+        connected = now;
+    }
+}
+
+/// Keep the latest winner shown upstream, also after Discord restarted.
+fn forward<C: RpcClient>(upstream: &C, updates: &mpsc::Receiver<Update>, state: &Mutex<State>) {
+    let mut wanted = None;
+    while let Ok(update) = updates.recv() {
+        // Only the last of several quick updates matters.
+        for update in Some(update).into_iter().chain(updates.try_iter()) {
+            if let Update::Winner(activity) = update {
+                wanted = activity;
+            }
+        }
+        // Applications connecting later get to see who is logged in.
+        if let Some(ready) = upstream.ready() {
+            state.lock().ready = Some(ready);
+        }
+        // Shown again once the watcher sees the client reconnect.
+        if !upstream.is_connected() {
+            continue;
+        }
+
+        let result = match wanted {
+            Some(ref activity) => upstream.set_activity(Activity::clone(activity)),
+            None => upstream.clear_activity(),
+        };
+        if let Err(why) = result {
+            warn!("Failed to forward presence: {}", why);
+        }
+    }
+}
+
+/// Tell the forwarder if the winner changed after `update` changed the claims.
+fn arbitrate<F>(state: &Mutex<State>, updates: &mpsc::Sender<Update>, update: F)
+    where
+        F: FnOnce(&mut State),
+{
+    let mut state = state.lock();
+    let before = state.winner().map(|claim| claim.activity.clone());
+    update(&mut state);
+    let after = state.winner().map(|claim| claim.activity.clone());
+
+    if before != after {
+        if let Some(claim) = state.winner() {
+            debug!("Showing the presence of {}", claim.client_id);
+        }
+        let _ = updates.send(Update::Winner(after.map(Box::new)));
+    }
+}
+
+fn release(id: usize, state: &Mutex<State>, updates: &mpsc::Sender<Update>) {
+    arbitrate(state, updates, |state| {
+        state.claims.remove(&id);
+    });
+}
+
+fn serve(
+    id: usize,
+    mut stream: UnixStream,
+    state: &Mutex<State>,
+    updates: &mpsc::Sender<Update>,
+) -> Result<()> {
+    let handshake = Message::read_from(&mut stream)?;
+    if handshake.opcode != OpCode::Handshake {
+        return Err(Error::Conversion);
+    }
+    let handshake: Value = serde_json::from_str(&handshake.payload)?;
+    let client_id = match handshake["client_id"] {
+        Value::String(ref id) => id.parse().unwrap_or(0),
+        ref id => id.as_u64().unwrap_or(0),
+    };
+    debug!("[{}] Handshake from {}", id, client_id);
+
+    let ready = Payload {
+        cmd: Command::Dispatch,
+        args: None,
+        data: Some(state.lock().ready.clone().unwrap_or_default()),
+        evt: Some(Event::Ready),
+        nonce: None,
+    };
+    Message::new(OpCode::Frame, ready).write_to(&mut stream)?;
+
+    loop {
+        let message = match Message::read_from(&mut stream) {
+            Ok(message) => message,
+            Err(Error::ConnectionClosed) => break,
+            Err(why) => return Err(why),
+        };
+        let reply = match message.opcode {
+            OpCode::Ping => Message::new(OpCode::Pong, serde_json::from_str::<Value>(&message.payload)?),
+            OpCode::Close => break,
+            OpCode::Frame => {
+                let payload: Value = serde_json::from_str(&message.payload)?;
+                Message::new(OpCode::Frame, command(id, client_id, &payload, state, updates))
+            }
+            _ => continue,
+        };
+        reply.write_to(&mut stream)?;
+    }
+
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(())
+}
+
+/// An `ERROR` reply to `cmd`, with Discord's error `code`.
+fn error(cmd: Command, code: u32, message: String, nonce: Option<String>) -> Payload<Value> {
+    let error = ErrorEvent {
+        code: Some(code),
+        message: Some(message),
+    };
+    Payload {
+        cmd,
+        args: None,
+        data: serde_json::to_value(error).ok(),
+        evt: Some(Event::Error),
+        nonce,
+    }
+}
+
+/// Answer a command the way Discord would.
+fn command(
+    id: usize,
+    client_id: u64,
+    payload: &Value,
+    state: &Mutex<State>,
+    updates: &mpsc::Sender<Update>,
+) -> Payload<Value> {
+    let nonce = payload["nonce"].as_str().map(String::from);
+    // Commands this crate has no model for are answered like a dispatched error.
+    let cmd = serde_json::from_value(payload["cmd"].clone()).unwrap_or(Command::Dispatch);
+    if cmd != Command::SetActivity {
+        let message = "Only SET_ACTIVITY is supported by the broker".to_string();
+        return error(cmd, 4002, message, nonce);
+    }
+
+    let activity = match payload["args"]["activity"] {
+        Value::Null => None,
+        ref activity => match serde_json::from_value::<Activity>(activity.clone()) {
+            Ok(activity) => Some(activity),
+            Err(why) => return error(cmd, 4000, why.to_string(), nonce),
+        },
+    };
+    let priority = match payload["args"]["priority"].as_i64().map(i32::try_from) {
+        Some(Ok(priority)) => Some(priority),
+        Some(Err(_)) => return error(cmd, 4000, "The priority does not fit in 32 bits".to_string(), nonce),
+        None => None,
+    };
+
+    arbitrate(state, updates, |state| match activity.clone() {
+        Some(activity) => {
+            state.sequence += 1;
+            let priority = priority
+                .or_else(|| state.priorities.get(&client_id).cloned())
+                .unwrap_or(0);
+            let claim = Claim {
+                client_id,
+                priority,
+                activity,
+                sequence: state.sequence,
+            };
+            state.claims.insert(id, claim);
+        }
+        None => {
+            state.claims.remove(&id);
+        }
+    });
+
+    Payload {
+        cmd,
+        args: None,
+        data: activity.and_then(|activity| serde_json::to_value(activity).ok()),
+        evt: None,
+        nonce,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use connection::test_utils::temp_dir;
+    use models::{
+        commands::{Subscription, SubscriptionArgs},
+        payload::Payload,
+        ActivityBuilder, Event,
+    };
+    use offline::PresenceRecorder;
+
+    use super::*;
+
+    fn connect(path: &Path, client_id: u64) -> UnixStream {
+        let mut stream = UnixStream::connect(path).unwrap();
+        let handshake = json![{ "v": 1, "client_id": client_id.to_string() }];
+        Message::new(OpCode::Handshake, handshake).write_to(&mut stream).unwrap();
+        Message::read_from(&mut stream).unwrap();
+        stream
+    }
+
+    /// Set an activity showing `details`, returning the reply.
+    fn set(stream: &mut UnixStream, details: &str, priority: Option<i64>) -> Value {
+        let activity = ActivityBuilder::default().details(details.to_string()).build().unwrap();
+        let payload = json![{
+            "cmd": "SET_ACTIVITY",
+            "args": { "pid": 1, "activity": activity, "priority": priority },
+            "nonce": "1",
+        }];
+        Message::new(OpCode::Frame, payload).write_to(stream).unwrap();
+        serde_json::from_str(&Message::read_from(stream).unwrap().payload).unwrap()
+    }
+
+    fn details(activity: Option<Activity>) -> Value {
+        serde_json::to_value(activity).unwrap()["details"].clone()
+    }
+
+    fn shown(broker: &Broker) -> Value {
+        details(broker.winner().map(|claim| claim.activity))
+    }
+
+    /// Tells the test about every activity forwarded upstream.
+    struct Observed(PresenceRecorder, mpsc::Sender<Value>);
+
+    impl RpcClient for Observed {
+        fn is_connected(&self) -> bool {
+            self.0.is_connected()
+        }
+
+        fn wait_for_connection_change(&self, connected: bool, timeout: Duration) -> bool {
+            self.0.wait_for_connection_change(connected, timeout)
+        }
+
+        fn set_activity(&self, activity: Activity) -> Result<Payload<Activity>> {
+            let details = details(Some(activity.clone()));
+            let result = self.0.set_activity(activity);
+            let _ = self.1.send(details);
+            result
+        }
+
+        fn clear_activity(&self) -> Result<Payload<Activity>> {
+            let result = self.0.clear_activity();
+            let _ = self.1.send(Value::Null);
+            result
+        }
+
+        fn send_activity_join_invite(&self, user_id: u64) -> Result<Payload<Value>> {
+            self.0.send_activity_join_invite(user_id)
+        }
+
+        fn close_activity_request(&self, user_id: u64) -> Result<Payload<Value>> {
+            self.0.close_activity_request(user_id)
+        }
+
+        fn subscribe(&self, evt: Event, args: SubscriptionArgs) -> Result<Payload<Subscription>> {
+            self.0.subscribe(evt, args)
+        }
+
+        fn unsubscribe(&self, evt: Event, args: SubscriptionArgs) -> Result<Payload<Subscription>> {
+            self.0.unsubscribe(evt, args)
+        }
+
+        fn recv_event(&self, timeout: Duration) -> Result<Payload<Value>> {
+            self.0.recv_event(timeout)
+        }
+    }
+
+    /// Wait until `details` were forwarded upstream, skipping older updates.
+    fn forwarded(updates: &mpsc::Receiver<Value>, details: &str) {
+        while updates.recv_timeout(Duration::from_secs(5)).unwrap() != details {}
+    }
+
+    #[test]
+    fn test_arbitration() {
+        let dir = temp_dir("broker");
+        let broker = Arc::new(Broker::bind(Some(dir.join("discord-ipc-0"))).unwrap().priority(3, 10));
+        let upstream = PresenceRecorder::new();
+        let (sender, updates) = mpsc::channel();
+        {
+            let broker = Arc::clone(&broker);
+            let upstream = Observed(upstream.clone(), sender);
+            thread::spawn(move || broker.run(upstream));
+        }
+
+        let mut editor = connect(broker.path(), 1);
+        let mut build = connect(broker.path(), 2);
+        set(&mut editor, "Editing", None);
+        assert_eq![shown(&broker), "Editing"];
+        set(&mut build, "Building", None);
+        assert_eq![shown(&broker), "Building"];
+
+        let mut launcher = connect(broker.path(), 3);
+        set(&mut launcher, "Playing", None);
+        set(&mut editor, "Still editing", None);
+        assert_eq![shown(&broker), "Playing"];
+        set(&mut editor, "Urgent", Some(20));
+        assert_eq![shown(&broker), "Urgent"];
+        forwarded(&updates, "Urgent");
+
+        // The winner changes before it is forwarded.
+        drop(editor);
+        drop(launcher);
+        forwarded(&updates, "Building");
+        assert_eq![shown(&broker), "Building"];
+        assert_eq![details(upstream.current_activity()), "Building"];
+
+        // Shown again once Discord is back.
+        upstream.set_connected(false);
+        set(&mut build, "Offline build", None);
+        upstream.set_connected(true);
+        forwarded(&updates, "Offline build");
+
+        let reply = set(&mut build, "Too urgent", Some(1 << 40));
+        assert_eq![reply["evt"], "ERROR"];
+        assert_eq![reply["data"]["code"], 4000];
+        assert_eq![shown(&broker), "Offline build"];
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+//! Handlers called by `Client::run_callbacks`, on the thread and at the time the application chooses.
+
+use serde_json;
+
+use models::{
+    events::{ErrorEvent, ReadyEvent},
+    payload::Payload,
+    Event, Message,
+};
+#[cfg(feature = "rich_presence")]
+use models::rich_presence::{ActivityJoinEvent, ActivityJoinRequestEvent, ActivitySpectateEvent};
+
+/// Something that happened to the connection, queued for the callbacks.
+pub(crate) enum Notice {
+    Ready(ReadyEvent),
+    Disconnected,
+    Error(ErrorEvent),
+}
+
+type Handler<T> = Option<Box<dyn FnMut(&T) + Send>>;
+
+#[derive(Default)]
+pub(crate) struct Handlers {
+    /// Every dispatched event, whatever its type.
+    pub event: Handler<Payload<serde_json::Value>>,
+    pub ready: Handler<ReadyEvent>,
+    pub disconnected: Option<Box<dyn FnMut() + Send>>,
+    pub error: Handler<ErrorEvent>,
+    #[cfg(feature = "rich_presence")]
+    pub activity_join: Handler<ActivityJoinEvent>,
+    #[cfg(feature = "rich_presence")]
+    pub activity_spectate: Handler<ActivitySpectateEvent>,
+    #[cfg(feature = "rich_presence")]
+    pub activity_join_request: Handler<ActivityJoinRequestEvent>,
+}
+
+impl Handlers {
+    pub fn notice(&mut self, notice: Notice) {
+        match notice {
+            Notice::Ready(ready) => call(&mut self.ready, &ready),
+            Notice::Disconnected => {
+                if let Some(ref mut handler) = self.disconnected {
+                    handler();
+                }
+            }
+            Notice::Error(error) => call(&mut self.error, &error),
+        }
+    }
+
+    /// Hand a dispatched event to its handler, events nobody handles are dropped.
+    pub fn event(&mut self, message: &Message) {
+        let event = match serde_json::from_str::<Payload<serde_json::Value>>(&message.payload) {
+            Ok(event) => event,
+            Err(why) => return warn!("Failed to parse event: {}", why),
+        };
+        call(&mut self.event, &event);
+        let data = event.data.unwrap_or_default();
+        match event.evt {
+            Some(Event::Error) => dispatch(&mut self.error, data),
+            #[cfg(feature = "rich_presence")]
+            Some(Event::ActivityJoin) => dispatch(&mut self.activity_join, data),
+            #[cfg(feature = "rich_presence")]
+            Some(Event::ActivitySpectate) => dispatch(&mut self.activity_spectate, data),
+            #[cfg(feature = "rich_presence")]
+            Some(Event::ActivityJoinRequest) => dispatch(&mut self.activity_join_request, data),
+            evt => debug!("No handler for {:?}", evt),
+        }
+    }
+}
+
+fn call<T>(handler: &mut Handler<T>, value: &T) {
+    if let Some(ref mut handler) = *handler {
+        handler(value);
+    }
+}
+
+fn dispatch<T>(handler: &mut Handler<T>, data: serde_json::Value)
+    where
+        T: ::serde::de::DeserializeOwned,
+{
+    if handler.is_none() {
+        return;
+    }
+    match serde_json::from_value(data) {
+        Ok(value) => call(handler, &value),
+        Err(why) => warn!("Failed to parse event data: {}", why),
+    }
+}
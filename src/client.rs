@@ -1,47 +1,187 @@
+#[cfg(feature = "rich_presence")]
+use std::collections::HashMap;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::{
+    path::Path,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
 use serde::{de::DeserializeOwned, Serialize};
 #[allow(unused)]
 use serde_json::Value;
 
-use connection::Manager as ConnectionManager;
+use callbacks::Handlers;
+use connection::{self, Manager as ConnectionManager, QueueConfig, Transport};
 use error::{Error, Result};
+use instances::Selector;
 use models::{
     Command,
     commands::{Subscription, SubscriptionArgs},
     Event,
+    events::{ErrorEvent, ReadyEvent},
     message::Message, OpCode, payload::Payload,
 };
+use stats::{Outcome, Stats};
+use transcript::{Replay, Transcript, TranscriptWriter};
+use utils;
 #[cfg(feature = "rich_presence")]
 use models::rich_presence::{
-    Activity, CloseActivityRequestArgs, SendActivityJoinInviteArgs, SetActivityArgs,
+    Activity, ActivityJoinEvent, ActivityJoinRequestEvent, ActivitySpectateEvent,
+    CloseActivityRequestArgs, SendActivityJoinInviteArgs, SetActivityArgs,
 };
 
 #[derive(Clone)]
 pub struct Client {
     connection_manager: ConnectionManager,
+    handlers: Arc<Mutex<Handlers>>,
+    subscriptions: Arc<Mutex<Vec<(Event, SubscriptionArgs)>>>,
+    /// The presence last set for every application, restored when switching back to it.
+    #[cfg(feature = "rich_presence")]
+    presences: Arc<Mutex<HashMap<u64, Activity>>>,
 }
 
 impl Client {
     pub fn new(client_id: u64) -> Self {
-        let connection_manager = ConnectionManager::new(client_id);
-        Self { connection_manager }
+        Self::with_manager(ConnectionManager::new(client_id))
+    }
+
+    fn with_manager(connection_manager: ConnectionManager) -> Self {
+        Self {
+            connection_manager,
+            handlers: Arc::new(Mutex::new(Handlers::default())),
+            subscriptions: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "rich_presence")]
+            presences: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Create a client that talks over a custom transport instead of the Discord IPC socket.
+    ///
+    /// `connect` is called every time a (re)connect is attempted.
+    pub fn with_transport<F>(client_id: u64, connect: F) -> Self
+        where
+            F: Fn() -> Result<Box<dyn Transport>> + Send + Sync + 'static,
+    {
+        Self::with_manager(ConnectionManager::with_connector(client_id, Arc::new(connect)))
+    }
+
+    /// Create a client that plays back a recorded session instead of talking to Discord.
+    pub fn replay(client_id: u64, transcript: Transcript) -> Self {
+        Self::with_transport(client_id, move || {
+            Ok(Box::new(Replay::new(transcript.clone())) as Box<dyn Transport>)
+        })
+    }
+
+    /// Record every message sent and received to a transcript file at `path`.
+    pub fn record<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let writer = TranscriptWriter::create(path)?;
+        self.connection_manager.record(Some(writer));
+        Ok(())
+    }
+
+    pub fn stop_recording(&self) {
+        self.connection_manager.record(None);
+    }
+
+    /// Limit the commands waiting to be sent while Discord is not reachable.
+    ///
+    /// By default 32 are kept, only the newest of every command and event.
+    /// Commands dropped from the queue fail with `Error::QueueFull`.
+    pub fn set_outbound_queue(&self, config: QueueConfig) {
+        self.connection_manager.configure_outbound(config);
+    }
+
+    /// Limit the events waiting for [`Client::recv_event`], [`Client::process`] or
+    /// [`Client::run_callbacks`]. By default the newest 64 are kept.
+    pub fn set_event_queue(&self, config: QueueConfig) {
+        self.connection_manager.configure_events(config);
     }
 
-    pub fn start(&mut self, retries: u32) {
+    pub fn start(&self, retries: u32) {
         self.connection_manager.start(retries);
     }
 
-    fn execute<A, E>(&mut self, cmd: Command, args: A, evt: Option<Event>) -> Result<Payload<E>>
+    /// Start like [`Client::start`], but wait up to `timeout` for the first attempt to connect
+    /// and return its error instead of retrying, e.g. `Error::HandshakeRejected` for an unknown
+    /// client id. Once connected, lost connections are retried as usual.
+    ///
+    /// If the first attempt takes longer than `timeout`, `Error::Timeout` is returned and the
+    /// connection thread stops as soon as the attempt is over, connected or not.
+    pub fn try_start(&self, retries: u32, timeout: Duration) -> Result<ReadyEvent> {
+        self.connection_manager.try_start(retries, timeout)
+    }
+
+    /// Close the connection and end the connection thread, for every clone of this client.
+    ///
+    /// Requests still waiting fail with `Error::ConnectionClosed`. The client can be started again.
+    pub fn stop(&self) {
+        self.connection_manager.stop();
+    }
+
+    /// Block until the client is connected and return the `READY` event.
+    ///
+    /// Fails with `Error::Timeout` after `timeout`, or with `Error::ConnectionClosed`
+    /// once the connection thread gave up after its retries.
+    pub fn wait_until_ready(&self, timeout: Duration) -> Result<ReadyEvent> {
+        self.connection_manager.wait_until_ready(timeout)
+    }
+
+    /// Block until the client connected, or lost its connection if it was `connected`,
+    /// and return whether it is connected now. Gives up after `timeout`.
+    pub fn wait_for_connection_change(&self, connected: bool, timeout: Duration) -> bool {
+        self.connection_manager.wait_for_connection_change(connected, timeout)
+    }
+
+    /// Connect without starting the connection thread, for applications with their own event loop.
+    ///
+    /// Wait for the client's file descriptor to become readable and call [`Client::process`]
+    /// to receive events. When the connection is lost, call this again to reconnect;
+    /// the new connection has a different file descriptor.
+    pub fn connect(&self) -> Result<()> {
+        self.connection_manager.connect()
+    }
+
+    /// Send queued messages and return the events that arrived, without blocking.
+    ///
+    /// Only needed when the client was connected with [`Client::connect`] instead of started,
+    /// commands wait for their responses by themselves.
+    pub fn process(&self) -> Result<Vec<Payload<Value>>> {
+        self.connection_manager.process()?;
+        self.connection_manager
+            .queued_events()
+            .into_iter()
+            .map(|Message { payload, .. }| Ok(serde_json::from_str(&payload)?))
+            .collect()
+    }
+
+    fn execute<A, E>(&self, cmd: Command, args: A, evt: Option<Event>) -> Result<Payload<E>>
         where
             A: Serialize + Send + Sync,
             E: Serialize + DeserializeOwned + Send + Sync,
     {
-        let message = Message::new(
-            OpCode::Frame,
-            Payload::with_nonce(cmd, Some(args), None, evt),
-        );
-        self.connection_manager.send(message)?;
-        let Message { payload, .. } = self.connection_manager.recv()?;
-        let response: Payload<E> = serde_json::from_str(&payload)?;
+        let payload = Payload::with_nonce(cmd, Some(args), None, evt);
+        let nonce = payload.nonce.clone().unwrap_or_default();
+        let span = span!("command", cmd = ?cmd, nonce = %nonce);
+        let _entered = span.enter();
+        #[cfg(feature = "tracing")]
+        let started = Instant::now();
+
+        let message = Message::new(OpCode::Frame, payload);
+        let response = self.connection_manager.request(&nonce, message);
+        event!(elapsed_ms = started.elapsed().as_millis() as u64, ok = response.is_ok(), "Command finished");
+        let response = response.and_then(|Message { payload, .. }| {
+            Ok(serde_json::from_str::<Payload<E>>(&payload)?)
+        });
+        self.connection_manager.record_command(match response {
+            Ok(Payload { evt: Some(Event::Error), .. }) => Outcome::Rejected,
+            Ok(_) => Outcome::Succeeded,
+            Err(_) => Outcome::Failed,
+        });
+        let response = response?;
 
         match response.evt {
             Some(Event::Error) => Err(Error::SubscriptionFailed),
@@ -49,25 +189,57 @@ impl Client {
         }
     }
 
+    /// Send a `PING` and wait for the `PONG`, returning the round trip time.
+    pub fn ping(&self) -> Result<Duration> {
+        if !self.is_connected() {
+            return Err(Error::ConnectionClosed);
+        }
+        let nonce = utils::nonce();
+        let started = Instant::now();
+        let Message { opcode, .. } = self
+            .connection_manager
+            .request(&nonce, connection::ping_message(&nonce))?;
+        match opcode {
+            OpCode::Pong => Ok(started.elapsed()),
+            _ => Err(Error::ConnectionClosed),
+        }
+    }
+
+    /// Counters of this client and its clones: frames, bytes, commands, reconnects and latency.
+    pub fn stats(&self) -> Stats {
+        self.connection_manager.stats()
+    }
+
     pub fn is_connected(&self) -> bool {
         self.connection_manager.is_connected()
     }
 
+    /// The `READY` event of the current connection, with the connected user and server configuration.
+    pub fn ready(&self) -> Option<ReadyEvent> {
+        self.connection_manager.ready()
+    }
+
     #[cfg(feature = "rich_presence")]
-    pub fn set_activity(&mut self, activity: Activity) -> Result<Payload<Activity>> {
-        self.execute(Command::SetActivity, SetActivityArgs::new(activity), None)
+    pub fn set_activity(&self, activity: Activity) -> Result<Payload<Activity>> {
+        let client_id = self.connection_manager.client_id();
+        let response = self.execute(Command::SetActivity, SetActivityArgs::new(activity.clone()), None)?;
+        self.presences.lock().insert(client_id, activity);
+        Ok(response)
     }
 
     #[cfg(feature = "rich_presence")]
-    pub fn clear_activity(&mut self) -> Result<Payload<Activity>> {
-        self.execute(Command::SetActivity, SetActivityArgs::default(), None)
+    pub fn clear_activity(&self) -> Result<Payload<Activity>> {
+        let client_id = self.connection_manager.client_id();
+        let response = self.execute(Command::SetActivity, SetActivityArgs::default(), None)?;
+        self.presences.lock().remove(&client_id);
+        Ok(response)
     }
 
     // NOTE: Not sure what the actual response values of
     //       SEND_ACTIVITY_JOIN_INVITE and CLOSE_ACTIVITY_REQUEST are,
     //       they are not documented.
     #[cfg(feature = "rich_presence")]
-    pub fn send_activity_join_invite(&mut self, user_id: u64) -> Result<Payload<Value>> {
+    pub fn send_activity_join_invite(&self, user_id: u64) -> Result<Payload<Value>> {
         self.execute(
             Command::SendActivityJoinInvite,
             SendActivityJoinInviteArgs::new(user_id),
@@ -76,7 +248,7 @@ impl Client {
     }
 
     #[cfg(feature = "rich_presence")]
-    pub fn close_activity_request(&mut self, user_id: u64) -> Result<Payload<Value>> {
+    pub fn close_activity_request(&self, user_id: u64) -> Result<Payload<Value>> {
         self.execute(
             Command::CloseActivityRequest,
             CloseActivityRequestArgs::new(user_id),
@@ -85,18 +257,480 @@ impl Client {
     }
 
     pub fn subscribe(
-        &mut self,
+        &self,
         evt: Event,
         args: SubscriptionArgs,
     ) -> Result<Payload<Subscription>> {
-        self.execute(Command::Subscribe, args, Some(evt))
+        let response = self.execute(Command::Subscribe, args.clone(), Some(evt))?;
+        let mut subscriptions = self.subscriptions.lock();
+        if !subscriptions.contains(&(evt, args.clone())) {
+            subscriptions.push((evt, args));
+        }
+        Ok(response)
     }
 
     pub fn unsubscribe(
-        &mut self,
+        &self,
         evt: Event,
         args: SubscriptionArgs,
     ) -> Result<Payload<Subscription>> {
-        self.execute(Command::Unsubscribe, args, Some(evt))
+        let response = self.execute(Command::Unsubscribe, args.clone(), Some(evt))?;
+        self.subscriptions.lock().retain(|subscription| *subscription != (evt, args.clone()));
+        Ok(response)
+    }
+
+    /// Switch to the Discord application `client_id`, for every clone of this client.
+    ///
+    /// Disconnects, repeats the handshake with the new id and subscribes to the same events again.
+    /// The presence last set for that application is restored, if there is one;
+    /// otherwise none is shown until [`Client::set_activity`] is called.
+    /// With the connection thread running, waits up to `timeout` for it to reconnect.
+    pub fn set_client_id(&self, client_id: u64, timeout: Duration) -> Result<ReadyEvent> {
+        let ready = self.connection_manager.set_client_id(client_id, timeout)?;
+        let subscriptions = self.subscriptions.lock().clone();
+        for (evt, args) in subscriptions {
+            self.subscribe(evt, args)?;
+        }
+        #[cfg(feature = "rich_presence")]
+        {
+            let presence = self.presences.lock().get(&client_id).cloned();
+            if let Some(activity) = presence {
+                self.set_activity(activity)?;
+            }
+        }
+        Ok(ready)
+    }
+
+    /// Choose which Discord instance to connect to when several are running,
+    /// e.g. by release channel or logged in user. Applies from the next connect on.
+    ///
+    /// Only for clients talking to the Discord IPC sockets, clients created with
+    /// [`Client::with_transport`] fail with `Error::CustomTransport`.
+    /// See [`instances::list`](::instances::list) for what is running.
+    pub fn select_instance(&self, selector: Option<Selector>) -> Result<()> {
+        self.connection_manager.select_instance(selector)
+    }
+
+    /// The id of the Discord application the client connects as.
+    pub fn client_id(&self) -> u64 {
+        self.connection_manager.client_id()
+    }
+
+    /// Call the registered handlers for everything that happened since the last call,
+    /// on the calling thread, e.g. once per frame of a game loop.
+    ///
+    /// Queued events without a handler are dropped, of connects, disconnects and
+    /// connection errors only the newest 16 are kept between calls.
+    /// Handlers must not register handlers themselves.
+    pub fn run_callbacks(&self) {
+        let notices = self.connection_manager.queued_notices();
+        let events = self.connection_manager.queued_events();
+        let mut handlers = self.handlers.lock();
+        for notice in notices {
+            handlers.notice(notice);
+        }
+        for event in events {
+            handlers.event(&event);
+        }
+    }
+
+    /// Wait up to `timeout` for something to call a handler for, then call them like [`Client::run_callbacks`],
+    /// for a thread that does nothing else. Returns right away once the connection thread stopped.
+    pub fn run_callbacks_timeout(&self, timeout: Duration) {
+        self.connection_manager.wait_for_callbacks(timeout);
+        self.run_callbacks();
+    }
+
+    /// Called by [`Client::run_callbacks`] after every connect, with the connected user.
+    pub fn on_ready<F>(&self, handler: F)
+        where
+            F: FnMut(&ReadyEvent) + Send + 'static,
+    {
+        self.handlers.lock().ready = Some(Box::new(handler));
+    }
+
+    /// Called by [`Client::run_callbacks`] when the connection was lost.
+    pub fn on_disconnected<F>(&self, handler: F)
+        where
+            F: FnMut() + Send + 'static,
+    {
+        self.handlers.lock().disconnected = Some(Box::new(handler));
+    }
+
+    /// Called by [`Client::run_callbacks`] for failed connection attempts and `ERROR` events.
+    pub fn on_error<F>(&self, handler: F)
+        where
+            F: FnMut(&ErrorEvent) + Send + 'static,
+    {
+        self.handlers.lock().error = Some(Box::new(handler));
+    }
+
+    /// Called by [`Client::run_callbacks`] for every event dispatched, before the handler for its type.
+    pub fn on_event<F>(&self, handler: F)
+        where
+            F: FnMut(&Payload<Value>) + Send + 'static,
+    {
+        self.handlers.lock().event = Some(Box::new(handler));
+    }
+
+    /// Called by [`Client::run_callbacks`] for `ACTIVITY_JOIN`, once subscribed to it.
+    #[cfg(feature = "rich_presence")]
+    pub fn on_activity_join<F>(&self, handler: F)
+        where
+            F: FnMut(&ActivityJoinEvent) + Send + 'static,
+    {
+        self.handlers.lock().activity_join = Some(Box::new(handler));
+    }
+
+    /// Called by [`Client::run_callbacks`] for `ACTIVITY_SPECTATE`, once subscribed to it.
+    #[cfg(feature = "rich_presence")]
+    pub fn on_activity_spectate<F>(&self, handler: F)
+        where
+            F: FnMut(&ActivitySpectateEvent) + Send + 'static,
+    {
+        self.handlers.lock().activity_spectate = Some(Box::new(handler));
+    }
+
+    /// Called by [`Client::run_callbacks`] for `ACTIVITY_JOIN_REQUEST`, once subscribed to it.
+    #[cfg(feature = "rich_presence")]
+    pub fn on_activity_join_request<F>(&self, handler: F)
+        where
+            F: FnMut(&ActivityJoinRequestEvent) + Send + 'static,
+    {
+        self.handlers.lock().activity_join_request = Some(Box::new(handler));
+    }
+
+    /// Wait for the next event Discord dispatched for one of our subscriptions.
+    pub fn recv_event(&self, timeout: Duration) -> Result<Payload<Value>> {
+        let Message { payload, .. } = self.connection_manager.recv_event(timeout)?;
+        Ok(serde_json::from_str(&payload)?)
+    }
+}
+
+/// The socket of the current connection, `-1` while disconnected.
+///
+/// Readable whenever [`Client::process`] has something to do.
+#[cfg(unix)]
+impl AsRawFd for Client {
+    fn as_raw_fd(&self) -> RawFd {
+        self.connection_manager.raw_fd().unwrap_or(-1)
+    }
+}
+
+/// The presence and subscription API of a [`Client`].
+///
+/// Application code can depend on this trait instead of [`Client`] directly,
+/// so it can be exercised without a running Discord instance
+/// (see [`PresenceRecorder`](crate::offline::PresenceRecorder)).
+pub trait RpcClient {
+    fn is_connected(&self) -> bool;
+
+    /// The `READY` event of the current connection, if there is one.
+    fn ready(&self) -> Option<ReadyEvent> {
+        None
+    }
+
+    /// Block until the connection state differs from `connected`, at most for `timeout`,
+    /// and return whether connected now, e.g. to notice that Discord restarted.
+    ///
+    /// The default implementation waits out the `timeout`.
+    fn wait_for_connection_change(&self, _connected: bool, timeout: Duration) -> bool {
+        thread::sleep(timeout);
+        self.is_connected()
+    }
+
+    #[cfg(feature = "rich_presence")]
+    fn set_activity(&self, activity: Activity) -> Result<Payload<Activity>>;
+
+    #[cfg(feature = "rich_presence")]
+    fn clear_activity(&self) -> Result<Payload<Activity>>;
+
+    #[cfg(feature = "rich_presence")]
+    fn send_activity_join_invite(&self, user_id: u64) -> Result<Payload<Value>>;
+
+    #[cfg(feature = "rich_presence")]
+    fn close_activity_request(&self, user_id: u64) -> Result<Payload<Value>>;
+
+    fn subscribe(&self, evt: Event, args: SubscriptionArgs) -> Result<Payload<Subscription>>;
+
+    fn unsubscribe(&self, evt: Event, args: SubscriptionArgs) -> Result<Payload<Subscription>>;
+
+    fn recv_event(&self, timeout: Duration) -> Result<Payload<Value>>;
+}
+
+impl RpcClient for Client {
+    fn is_connected(&self) -> bool {
+        Client::is_connected(self)
+    }
+
+    fn ready(&self) -> Option<ReadyEvent> {
+        Client::ready(self)
+    }
+
+    fn wait_for_connection_change(&self, connected: bool, timeout: Duration) -> bool {
+        Client::wait_for_connection_change(self, connected, timeout)
+    }
+
+    #[cfg(feature = "rich_presence")]
+    fn set_activity(&self, activity: Activity) -> Result<Payload<Activity>> {
+        Client::set_activity(self, activity)
+    }
+
+    #[cfg(feature = "rich_presence")]
+    fn clear_activity(&self) -> Result<Payload<Activity>> {
+        Client::clear_activity(self)
+    }
+
+    #[cfg(feature = "rich_presence")]
+    fn send_activity_join_invite(&self, user_id: u64) -> Result<Payload<Value>> {
+        Client::send_activity_join_invite(self, user_id)
+    }
+
+    #[cfg(feature = "rich_presence")]
+    fn close_activity_request(&self, user_id: u64) -> Result<Payload<Value>> {
+        Client::close_activity_request(self, user_id)
+    }
+
+    fn subscribe(&self, evt: Event, args: SubscriptionArgs) -> Result<Payload<Subscription>> {
+        Client::subscribe(self, evt, args)
+    }
+
+    fn unsubscribe(&self, evt: Event, args: SubscriptionArgs) -> Result<Payload<Subscription>> {
+        Client::unsubscribe(self, evt, args)
+    }
+
+    fn recv_event(&self, timeout: Duration) -> Result<Payload<Value>> {
+        Client::recv_event(self, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io, thread,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Instant, UNIX_EPOCH},
+    };
+
+    use models::commands::SubscriptionArgs;
+    use providers::{self, Clock, VirtualClock};
+    use transcript::JOIN_SESSION;
+
+    use super::*;
+
+    #[cfg(feature = "rich_presence")]
+    #[test]
+    fn test_process_without_thread() {
+        let transcript = Transcript::from_reader(JOIN_SESSION.as_bytes()).unwrap();
+        let client = Client::replay(1, transcript);
+        assert_eq![client.process().unwrap_err().to_string(), "Connection closed"];
+
+        client.connect().unwrap();
+        let subscription = client.subscribe(Event::ActivityJoin, SubscriptionArgs::default()).unwrap();
+        assert_eq![subscription.evt, Some(Event::ActivityJoin)];
+
+        let events = client.process().unwrap();
+        assert_eq![events.len(), 1];
+        assert_eq![events[0].data, Some(json![{ "secret": "abc" }])];
+        assert!(client.process().unwrap().is_empty());
+        assert_eq![client.stats().commands.succeeded, 1];
+    }
+
+    #[test]
+    fn test_try_start() {
+        let transcript = Transcript::from_reader(JOIN_SESSION.as_bytes()).unwrap();
+        let client = Client::replay(1, transcript);
+        let ready = client.try_start(1, Duration::from_secs(5)).unwrap();
+        assert_eq![ready.v, Some(1)];
+        assert_eq![client.wait_until_ready(Duration::from_secs(5)).unwrap().v, Some(1)];
+        assert!(client.is_connected());
+
+        let rejected = r#"
+{"timestamp":1,"direction":"sent","opcode":"HANDSHAKE","payload":{"client_id":"1","v":1}}
+{"timestamp":2,"direction":"received","opcode":"CLOSE","payload":{"code":4000,"message":"Invalid Client ID"}}
+"#;
+        let client = Client::replay(1, Transcript::from_reader(rejected.as_bytes()).unwrap());
+        match client.try_start(3, Duration::from_secs(5)) {
+            Err(Error::HandshakeRejected(error)) => assert_eq![error.code, Some(4000)],
+            other => panic!("Expected the handshake to be rejected, got {:?}", other),
+        }
+        match client.wait_until_ready(Duration::from_secs(5)) {
+            Err(Error::ConnectionClosed) => (),
+            other => panic!("Expected the client to give up, got {:?}", other),
+        }
+
+        // Connecting takes longer than the caller waits, the thread stops afterwards.
+        let slow = Transcript::from_reader(JOIN_SESSION.as_bytes()).unwrap();
+        let client = Client::with_transport(1, move || {
+            thread::sleep(Duration::from_millis(200));
+            Ok(Box::new(Replay::new(slow.clone())) as Box<dyn Transport>)
+        });
+        match client.try_start(3, Duration::from_millis(10)) {
+            Err(Error::Timeout(_)) => (),
+            other => panic!("Expected to time out, got {:?}", other),
+        }
+        // It is connected for a moment, until it notices nobody waits any more.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            match client.wait_until_ready(Duration::from_secs(5)) {
+                Ok(_) => assert!(Instant::now() < deadline, "The connection thread kept running"),
+                Err(Error::ConnectionClosed) => break,
+                other => panic!("Expected the client to stop, got {:?}", other),
+            }
+        }
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn test_reconnect_with_virtual_clock() {
+        let clock = Arc::new(VirtualClock::default());
+        providers::set_clock(Arc::clone(&clock));
+
+        let transcript = Transcript::from_reader(JOIN_SESSION.as_bytes()).unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&attempts);
+        let client = Client::with_transport(1, move || {
+            if counter.fetch_add(1, Ordering::SeqCst) < 3 {
+                return Err(io::Error::from(io::ErrorKind::ConnectionRefused).into());
+            }
+            Ok(Box::new(Replay::new(transcript.clone())) as Box<dyn Transport>)
+        });
+
+        // Three failed attempts would wait 15 seconds on the system clock.
+        let started = Instant::now();
+        client.start(5);
+        client.wait_until_ready(Duration::from_secs(5)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq![attempts.load(Ordering::SeqCst), 4];
+        assert_eq![clock.now(), UNIX_EPOCH + Duration::from_secs(15)];
+    }
+
+    /// Answers every frame with itself, the newest first.
+    struct Echo(Vec<Message>);
+
+    impl Transport for Echo {
+        fn send(&mut self, message: Message) -> Result<()> {
+            self.0.push(message);
+            Ok(())
+        }
+
+        fn recv(&mut self) -> Result<Message> {
+            self.0
+                .pop()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::WouldBlock, "Nothing to echo").into())
+        }
+    }
+
+    /// Echoes and keeps a copy of everything sent.
+    struct Logged(Echo, Arc<Mutex<Vec<Message>>>);
+
+    impl Transport for Logged {
+        fn send(&mut self, message: Message) -> Result<()> {
+            self.1.lock().push(message.clone());
+            self.0.send(message)
+        }
+
+        fn recv(&mut self) -> Result<Message> {
+            self.0.recv()
+        }
+    }
+
+    #[cfg(feature = "rich_presence")]
+    #[test]
+    fn test_concurrent_replies() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Client>();
+
+        let client = Client::with_transport(1, || Ok(Box::new(Echo(Vec::new())) as Box<dyn Transport>));
+        client.start(1);
+        let threads: Vec<_> = (0..8)
+            .map(|user_id| {
+                let client = client.clone();
+                thread::spawn(move || {
+                    let reply = client.send_activity_join_invite(user_id).unwrap();
+                    assert_eq![reply.args.unwrap()["user_id"], user_id.to_string()];
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq![client.stats().commands.succeeded, 8];
+    }
+
+    #[test]
+    fn test_request_while_disconnected() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&sent);
+        let client = Client::with_transport(1, move || {
+            Ok(Box::new(Logged(Echo(Vec::new()), Arc::clone(&log))) as Box<dyn Transport>)
+        });
+
+        match client.subscribe(Event::Error, SubscriptionArgs::default()) {
+            Err(Error::ConnectionClosed) => (),
+            other => panic!("Expected the request to fail, got {:?}", other),
+        }
+        // The failed request is not sent once connected.
+        client.connect().unwrap();
+        client.subscribe(Event::Ready, SubscriptionArgs::default()).unwrap();
+        let events: Vec<_> = sent
+            .lock()
+            .iter()
+            .filter(|message| message.opcode == OpCode::Frame)
+            .map(|message| serde_json::from_str::<Value>(&message.payload).unwrap()["evt"].take())
+            .collect();
+        assert_eq![events, vec![json!["READY"]]];
+    }
+
+    #[cfg(feature = "rich_presence")]
+    #[test]
+    fn test_set_client_id() {
+        use models::rich_presence::ActivityBuilder;
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&sent);
+        let client = Client::with_transport(1, move || {
+            Ok(Box::new(Logged(Echo(Vec::new()), Arc::clone(&log))) as Box<dyn Transport>)
+        });
+        let sent_to = |opcode: OpCode, field: &str| -> Vec<Value> {
+            sent.lock()
+                .drain(..)
+                .filter(|message| message.opcode == opcode)
+                .map(|message| serde_json::from_str::<Value>(&message.payload).unwrap()[field].take())
+                .collect()
+        };
+
+        client.try_start(1, Duration::from_secs(5)).unwrap();
+        client.subscribe(Event::ActivityJoin, SubscriptionArgs::default()).unwrap();
+        let activity = ActivityBuilder::default().details("Game one".to_string()).build().unwrap();
+        client.set_activity(activity).unwrap();
+        sent.lock().clear();
+
+        client.clone().set_client_id(2, Duration::from_secs(5)).unwrap();
+        assert_eq![client.client_id(), 2];
+        assert_eq![sent_to(OpCode::Handshake, "client_id"), vec![json!["2"]]];
+        client.set_client_id(1, Duration::from_secs(5)).unwrap();
+        assert_eq![sent_to(OpCode::Frame, "cmd"), vec![json!["SUBSCRIBE"], json!["SET_ACTIVITY"]]];
+    }
+
+    #[cfg(feature = "rich_presence")]
+    #[test]
+    fn test_run_callbacks() {
+        use std::sync::mpsc;
+
+        let transcript = Transcript::from_reader(JOIN_SESSION.as_bytes()).unwrap();
+        let client = Client::replay(1, transcript);
+        let (sender, receiver) = mpsc::channel();
+        let (ready, join) = (sender.clone(), sender);
+        client.on_ready(move |event| ready.send(format!("ready v{}", event.v.unwrap())).unwrap());
+        client.on_activity_join(move |event| join.send(event.secret.clone().unwrap()).unwrap());
+
+        client.connect().unwrap();
+        client.subscribe(Event::ActivityJoin, SubscriptionArgs::default()).unwrap();
+        client.connection_manager.process().unwrap();
+        assert!(receiver.try_recv().is_err());
+
+        client.run_callbacks();
+        assert_eq![receiver.try_iter().collect::<Vec<_>>(), vec!["ready v1", "abc"]];
     }
 }
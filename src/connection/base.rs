@@ -2,26 +2,32 @@ use std::{
     io::{ErrorKind, Read, Write},
     marker::Sized,
     path::PathBuf,
-    thread, time,
+    sync::mpsc::RecvTimeoutError,
+    thread,
+    time,
 };
 
+use byteorder::{ByteOrder, LittleEndian};
 use bytes::BytesMut;
 
 use error::{Error, Result};
 use models::message::{Message, OpCode};
+use redact::Redacted;
 use utils;
 
+use super::poll::{self, Interest, RawFd};
+
 /// Wait for a non-blocking connection until it's complete.
 macro_rules! try_until_done {
-    [ $e:expr ] => {
+    [ $e:expr, $fd:expr, $interest:expr ] => {
         loop {
             match $e {
-                Ok(_) => break,
+                Ok(value) => break value,
                 Err(Error::IoError(ref err)) if err.kind() == ErrorKind::WouldBlock => (),
                 Err(why) => return Err(why),
             }
 
-            thread::sleep(time::Duration::from_millis(500));
+            poll::wait($fd, $interest, None)?;
         }
     }
 }
@@ -35,26 +41,31 @@ pub trait Connection: Sized {
 
     fn connect() -> Result<Self>;
 
+    /// The file descriptor to wait on for the connection to become ready, if there is one.
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+
     fn socket_path(n: u8) -> PathBuf {
         Self::ipc_path().join(format!("discord-ipc-{}", n))
     }
 
+    /// Every path Discord might listen on, in the order they should be tried.
+    fn socket_paths() -> Vec<PathBuf> {
+        (0..10).map(Self::socket_path).collect()
+    }
+
     fn handshake(&mut self, client_id: u64) -> Result<()> {
-        let hs = json![{
-            "client_id": client_id.to_string(),
-            "v": 1,
-            "nonce": utils::nonce()
-        }];
+        let hs = handshake_message(client_id);
 
-        try_until_done!(self.send(Message::new(OpCode::Handshake, hs.clone())));
-        try_until_done!(self.recv());
+        try_until_done!(self.send(hs.clone()), self.raw_fd(), Interest::Write);
+        try_until_done!(self.recv(), self.raw_fd(), Interest::Read);
 
         Ok(())
     }
 
     fn ping(&mut self) -> Result<OpCode> {
-        let message = Message::new(OpCode::Ping, json![{}]);
-        self.send(message)?;
+        self.send(ping_message(&utils::nonce()))?;
         let response = self.recv()?;
         Ok(response.opcode)
     }
@@ -63,27 +74,151 @@ pub trait Connection: Sized {
         match message.encode() {
             Err(why) => error!("{:?}", why),
             Ok(bytes) => {
-                self.socket().write_all(bytes.as_ref())?;
+                let fd = self.raw_fd();
+                write_remaining(self.socket(), fd, bytes.as_ref())?;
             }
         };
-        debug!("-> {:?}", message);
+        debug!("-> {:?}", Redacted(&message));
+        event!(opcode = ?message.opcode, bytes = message.payload.len(), "Frame sent");
         Ok(())
     }
 
     fn recv(&mut self) -> Result<Message> {
-        let mut buf = BytesMut::new();
-        buf.resize(1024, 0);
-        let n = self.socket().read(&mut buf)?;
-        debug!("Received {} bytes", n);
+        let fd = self.raw_fd();
+        let mut header = [0; 8];
+        let n = self.socket().read(&mut header)?;
 
         if n == 0 {
             return Err(Error::ConnectionClosed);
         }
 
-        buf = buf.split_to(n);
+        // Once a frame started arriving, wait for all of it, so frames never get split or merged.
+        read_remaining(self.socket(), fd, &mut header[n..])?;
+        let len = LittleEndian::read_u32(&header[4..]) as usize;
+        let mut buf = BytesMut::new();
+        buf.resize(header.len() + len, 0);
+        buf[..header.len()].copy_from_slice(&header);
+        read_remaining(self.socket(), fd, &mut buf[header.len()..])?;
+        debug!("Received {} bytes", buf.len());
+
         let message = Message::decode(&buf)?;
-        debug!("<- {:?}", message);
+        debug!("<- {:?}", Redacted(&message));
+        event!(opcode = ?message.opcode, bytes = message.payload.len(), "Frame received");
 
         Ok(message)
     }
 }
+
+/// An established connection messages can be exchanged over.
+///
+/// Every [`Connection`] is a transport, other implementations can be
+/// plugged into a [`Client`](crate::Client) with `Client::with_transport`.
+pub trait Transport: Send {
+    fn send(&mut self, message: Message) -> Result<()>;
+
+    fn recv(&mut self) -> Result<Message>;
+
+    /// The file descriptor that becomes readable when a message arrives.
+    ///
+    /// Without one, the transport is checked for messages every 500 ms.
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    /// Perform the handshake and return the `READY` message.
+    fn handshake(&mut self, client_id: u64) -> Result<Message> {
+        let hs = handshake_message(client_id);
+
+        try_until_done!(self.send(hs.clone()), self.raw_fd(), Interest::Write);
+        let ready = try_until_done!(self.recv(), self.raw_fd(), Interest::Read);
+
+        Ok(ready)
+    }
+}
+
+impl<C> Transport for C
+    where
+        C: Connection + Send,
+{
+    fn send(&mut self, message: Message) -> Result<()> {
+        Connection::send(self, message)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        Connection::recv(self)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Connection::raw_fd(self)
+    }
+}
+
+/// Once a frame started going out, all of it has to, or the next frame would be garbled.
+fn write_remaining<W: Write>(socket: &mut W, fd: Option<RawFd>, buf: &[u8]) -> Result<()> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match socket.write(&buf[pos..]) {
+            Ok(0) => return Err(Error::ConnectionClosed),
+            Ok(n) => pos += n,
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
+                poll::wait(fd, Interest::Write, None)?
+            }
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+fn read_remaining<R: Read>(socket: &mut R, fd: Option<RawFd>, buf: &mut [u8]) -> Result<()> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match socket.read(&mut buf[pos..]) {
+            Ok(0) => return Err(Error::ConnectionClosed),
+            Ok(n) => pos += n,
+            // The rest of a frame follows right away, don't wait `poll::FALLBACK_INTERVAL` for it.
+            Err(ref err) if err.kind() == ErrorKind::WouldBlock => match fd {
+                Some(_) => poll::wait(fd, Interest::Read, None)?,
+                None => thread::sleep(time::Duration::from_millis(1)),
+            },
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Perform the handshake like [`Transport::handshake`], but fail with `Error::Timeout`
+/// if there is no reply within `timeout`, e.g. from an instance that hangs.
+pub(crate) fn handshake_within<T>(transport: &mut T, client_id: u64, timeout: time::Duration) -> Result<Message>
+    where
+        T: Transport + ?Sized,
+{
+    let deadline = time::Instant::now() + timeout;
+    try_until_done!(transport.send(handshake_message(client_id)), transport.raw_fd(), Interest::Write);
+    loop {
+        match transport.recv() {
+            Err(Error::IoError(ref err)) if err.kind() == ErrorKind::WouldBlock => (),
+            result => return result,
+        }
+        let now = time::Instant::now();
+        if now >= deadline {
+            return Err(Error::Timeout(RecvTimeoutError::Timeout));
+        }
+        poll::wait(transport.raw_fd(), Interest::Read, Some(deadline - now))?;
+    }
+}
+
+fn handshake_message(client_id: u64) -> Message {
+    let hs = json![{
+        "client_id": client_id.to_string(),
+        "v": 1,
+        "nonce": utils::nonce()
+    }];
+    Message::new(OpCode::Handshake, hs)
+}
+
+/// A `PING`, Discord answers with a `PONG` carrying the same payload.
+pub(crate) fn ping_message(nonce: &str) -> Message {
+    Message::new(OpCode::Ping, json![{ "nonce": nonce }])
+}
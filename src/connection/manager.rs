@@ -1,99 +1,544 @@
-use std::{io::ErrorKind, sync::Arc, thread, time};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, ErrorKind},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
+    },
+    thread,
+    time::{self, Instant},
+};
 
-use crossbeam_channel::{Receiver, Sender, unbounded};
-use parking_lot::Mutex;
+use crossbeam_channel::{bounded, Sender};
+use parking_lot::{Condvar, Mutex};
+use serde_json::{self, Value};
 
+use callbacks::Notice;
 use error::{Error, Result};
-use models::Message;
+use instances::Selector;
+use models::{payload::Payload, ErrorEvent, Message, OpCode, ReadyEvent};
+use providers::Providers;
+use redact::Redacted;
+use stats::{Metered, Outcome, Recorder, Stats};
+use transcript::{Recording, TranscriptWriter};
+use utils;
 
-use super::{Connection, SocketConnection};
+use super::{
+    poll::{self, Interest, RawFd, Waker},
+    queue::{Overflow, Queue, QueueConfig},
+    Connection, SocketConnection, Transport,
+};
 
-type Tx = Sender<Message>;
-type Rx = Receiver<Message>;
+/// How many notices are kept for `Client::run_callbacks`, older ones are dropped.
+const NOTICES_CAPACITY: usize = 16;
+
+/// How long a request waits for its response.
+const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+/// Opens a new transport every time the manager (re)connects.
+pub type Connector = Arc<dyn Fn() -> Result<Box<dyn Transport>> + Send + Sync>;
+
+/// Where the connection thread reports how its first attempt to connect went,
+/// `None` once the caller gave up waiting.
+type FirstAttempt = Arc<Mutex<Option<Sender<Result<ReadyEvent>>>>>;
+
+/// A caller waiting for the response to its request, and whether the request went out yet.
+struct Pending {
+    reply: Sender<Result<Message>>,
+    sent: bool,
+}
 
 #[derive(Clone)]
 pub struct Manager {
-    connection: Arc<Mutex<Option<SocketConnection>>>,
-    client_id: u64,
-    outbound: (Rx, Tx),
-    inbound: (Rx, Tx),
-    handshake_completed: bool,
+    connection: Arc<Mutex<Option<Box<dyn Transport>>>>,
+    connector: Connector,
+    /// Whether the connector opens the Discord IPC socket, only then an instance can be selected.
+    sockets: bool,
+    recorder: Arc<Mutex<Option<TranscriptWriter>>>,
+    ready: Arc<Mutex<Option<ReadyEvent>>>,
+    ready_changed: Arc<Condvar>,
+    stats: Arc<Mutex<Recorder>>,
+    client_id: Arc<AtomicU64>,
+    selector: Arc<Mutex<Option<Selector>>>,
+    outbound: Arc<Queue>,
+    pending: Arc<Mutex<HashMap<String, Pending>>>,
+    events: Arc<Queue>,
+    notices: Arc<Mutex<VecDeque<Notice>>>,
+    /// Signalled with the notices locked when a notice or event is queued, or the connection thread stopped.
+    callbacks_queued: Arc<Condvar>,
+    /// `None` if it could not be created, the connection is checked every [`poll::FALLBACK_INTERVAL`] then.
+    waker: Arc<Option<Waker>>,
+    started: Arc<AtomicBool>,
+    /// Asks the connection thread to close the connection and end.
+    stopping: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    connecting: Arc<Mutex<()>>,
 }
 
 impl Manager {
     pub fn new(client_id: u64) -> Self {
+        Self {
+            sockets: true,
+            ..Self::with_connector(
+                client_id,
+                Arc::new(|| Ok(Box::new(SocketConnection::connect()?) as Box<dyn Transport>)),
+            )
+        }
+    }
+
+    pub fn with_connector(client_id: u64, connector: Connector) -> Self {
         let connection = Arc::new(Mutex::new(None));
-        let (sender_o, receiver_o) = unbounded();
-        let (sender_i, receiver_i) = unbounded();
 
         Self {
             connection,
-            client_id,
-            handshake_completed: false,
-            inbound: (receiver_i, sender_i),
-            outbound: (receiver_o, sender_o),
+            connector,
+            sockets: false,
+            recorder: Arc::new(Mutex::new(None)),
+            ready: Arc::new(Mutex::new(None)),
+            ready_changed: Arc::new(Condvar::new()),
+            stats: Arc::new(Mutex::new(Recorder::default())),
+            client_id: Arc::new(AtomicU64::new(client_id)),
+            selector: Arc::new(Mutex::new(None)),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            outbound: Arc::new(Queue::new(QueueConfig::new(32, Overflow::KeepNewest))),
+            events: Arc::new(Queue::new(QueueConfig::new(64, Overflow::DropOldest))),
+            notices: Arc::new(Mutex::new(VecDeque::new())),
+            callbacks_queued: Arc::new(Condvar::new()),
+            waker: Arc::new(
+                Waker::new()
+                    .map_err(|why| error!("Failed to create waker: {}", why))
+                    .ok(),
+            ),
+            started: Arc::new(AtomicBool::new(false)),
+            stopping: Arc::new(AtomicBool::new(false)),
+            stopped: Arc::new(AtomicBool::new(false)),
+            connecting: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn start(&self, retries: u32) {
+        self.spawn(retries, None);
+    }
+
+    /// Start the connection thread, but give up if the first attempt to connect fails.
+    ///
+    /// If the attempt takes longer than `timeout`, the thread stops once it is done.
+    pub fn try_start(&self, retries: u32, timeout: time::Duration) -> Result<ReadyEvent> {
+        let (sender, receiver) = bounded(1);
+        let first = Arc::new(Mutex::new(Some(sender)));
+        self.spawn(retries, Some(Arc::clone(&first)));
+        match receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(why) => {
+                // Tell the thread nobody waits any more, unless it reported just now.
+                if first.lock().take().is_some() {
+                    return Err(why.into());
+                }
+                receiver.recv().map_err(Error::RecvError)?
+            }
         }
     }
 
-    pub fn start(&mut self, retries: u32) {
+    fn spawn(&self, retries: u32, first: Option<FirstAttempt>) {
+        self.started.store(true, Ordering::SeqCst);
+        self.stopping.store(false, Ordering::SeqCst);
+        self.stopped.store(false, Ordering::SeqCst);
         let manager_inner = self.clone();
+        let providers = Providers::current();
         thread::spawn(move || {
-            send_and_receive_loop(manager_inner, retries);
+            providers.install();
+            send_and_receive_loop(&manager_inner, retries, first);
+            if manager_inner.stopping.load(Ordering::SeqCst) && manager_inner.is_connected() {
+                manager_inner.disconnect();
+            }
+            // Nobody answers commands any more, later ones are sent and received by themselves.
+            manager_inner.started.store(false, Ordering::SeqCst);
+            for (_, pending) in manager_inner.pending.lock().drain() {
+                let _ = pending.reply.send(Err(Error::ConnectionClosed));
+            }
+            manager_inner.stopped.store(true, Ordering::SeqCst);
+            {
+                let _ready = manager_inner.ready.lock();
+                manager_inner.ready_changed.notify_all();
+            }
+            let _notices = manager_inner.notices.lock();
+            manager_inner.callbacks_queued.notify_all();
+        });
+    }
+
+    /// Close the connection and end the connection thread, if there is one.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if self.started.load(Ordering::SeqCst) {
+            self.wake();
+        } else if self.is_connected() {
+            self.disconnect();
+        }
+    }
+
+    /// Wait until connected and return the `READY` event,
+    /// fails when the connection thread gave up.
+    pub fn wait_until_ready(&self, timeout: time::Duration) -> Result<ReadyEvent> {
+        let deadline = Instant::now() + timeout;
+        let mut ready = self.ready.lock();
+        loop {
+            if let Some(ref ready) = *ready {
+                return Ok(ready.clone());
+            }
+            if self.stopped.load(Ordering::SeqCst) {
+                return Err(Error::ConnectionClosed);
+            }
+            if self.ready_changed.wait_until(&mut ready, deadline).timed_out() {
+                return Err(Error::Timeout(RecvTimeoutError::Timeout));
+            }
+        }
+    }
+
+    /// Wait up to `timeout` until the client connected or lost its connection,
+    /// depending on whether it was `connected`, and return whether it is connected now.
+    pub fn wait_for_connection_change(&self, connected: bool, timeout: time::Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut ready = self.ready.lock();
+        while ready.is_some() == connected {
+            if self.ready_changed.wait_until(&mut ready, deadline).timed_out() {
+                break;
+            }
+        }
+        ready.is_some()
+    }
+
+    /// Send a request and wait up to [`REQUEST_TIMEOUT`] for the response carrying the same `nonce`.
+    pub fn request(&self, nonce: &str, message: Message) -> Result<Message> {
+        let (reply, response) = bounded(1);
+        self.pending
+            .lock()
+            .insert(nonce.to_string(), Pending { reply, sent: false });
+
+        let result = self.send(message).and_then(|_| {
+            if self.started.load(Ordering::SeqCst) {
+                return response.recv_timeout(REQUEST_TIMEOUT)?;
+            }
+            // Without the connection thread, nobody else would receive the response.
+            let deadline = Instant::now() + REQUEST_TIMEOUT;
+            loop {
+                self.process()?;
+                if let Ok(result) = response.try_recv() {
+                    return result;
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::Timeout(RecvTimeoutError::Timeout));
+                }
+                poll::wait(self.raw_fd(), Interest::Read, Some(deadline - now))?;
+            }
         });
+        self.pending.lock().remove(nonce);
+        if result.is_err() {
+            // Otherwise it would still go out later, with a nonce nobody waits for.
+            self.outbound.remove(|queued| nonce_of(queued).as_deref() == Some(nonce));
+        }
+        result
     }
 
-    pub fn send(&self, message: Message) -> Result<()> {
-        self.outbound
-            .1
-            .send(message)
-            .map_err(|err| Error::SendError(err))?;
+    fn send(&self, message: Message) -> Result<()> {
+        for dropped in self.outbound.push(message)? {
+            debug!("Dropped from the outbound queue: {:?}", Redacted(&dropped));
+            self.respond(nonce_of(&dropped), Err(Error::QueueFull));
+        }
+        self.wake();
         Ok(())
     }
 
-    pub fn recv(&self) -> Result<Message> {
-        let message = self.inbound.0.recv().map_err(|err| Error::RecvError(err))?;
-        Ok(message)
+    /// Interrupt the connection thread waiting for the connection.
+    fn wake(&self) {
+        if let Some(ref waker) = *self.waker {
+            waker.wake();
+        }
+    }
+
+    /// Hand a response to whoever waits for the request with the same nonce.
+    fn respond(&self, nonce: Option<String>, response: Result<Message>) {
+        match nonce.as_ref().and_then(|nonce| self.pending.lock().remove(nonce)) {
+            // The caller might have given up already.
+            Some(pending) => drop(pending.reply.send(response)),
+            None => debug!("Nobody is waiting for a response to {:?}", nonce),
+        }
+    }
+
+    /// Limit the messages waiting to be sent, e.g. while Discord is not running.
+    pub fn configure_outbound(&self, config: QueueConfig) {
+        for dropped in self.outbound.configure(config) {
+            self.respond(nonce_of(&dropped), Err(Error::QueueFull));
+        }
+    }
+
+    /// Limit the events waiting to be handed out.
+    pub fn configure_events(&self, config: QueueConfig) {
+        self.events.configure(config);
+    }
+
+    pub fn recv_event(&self, timeout: time::Duration) -> Result<Message> {
+        if !self.started.load(Ordering::SeqCst) {
+            let deadline = Instant::now() + timeout;
+            loop {
+                self.process()?;
+                if let Some(message) = self.events.try_pop() {
+                    return Ok(message);
+                }
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(Error::Timeout(RecvTimeoutError::Timeout));
+                }
+                poll::wait(self.raw_fd(), Interest::Read, Some(deadline - now))?;
+            }
+        }
+        self.events
+            .pop_timeout(timeout)
+            .ok_or(Error::Timeout(RecvTimeoutError::Timeout))
+    }
+
+    /// Events that were received but not handed out yet.
+    pub fn queued_events(&self) -> Vec<Message> {
+        self.events.drain()
+    }
+
+    /// Connects, disconnects and connection errors since the last call, at most the newest
+    /// [`NOTICES_CAPACITY`], so a client that stays offline doesn't pile them up.
+    pub fn queued_notices(&self) -> Vec<Notice> {
+        self.notices.lock().drain(..).collect()
+    }
+
+    fn notify(&self, notice: Notice) {
+        let mut notices = self.notices.lock();
+        if notices.len() == NOTICES_CAPACITY {
+            notices.pop_front();
+        }
+        notices.push_back(notice);
+        self.callbacks_queued.notify_all();
     }
 
-    fn connect(&mut self) -> Result<()> {
+    /// Wait up to `timeout` until there is a notice or an event for the callbacks,
+    /// returns right away once the connection thread stopped.
+    pub fn wait_for_callbacks(&self, timeout: time::Duration) {
+        if !self.started.load(Ordering::SeqCst) {
+            // Nobody else receives, like `recv_event` without the connection thread.
+            if self.events.is_empty() && self.notices.lock().is_empty() {
+                let _ = poll::wait(self.raw_fd(), Interest::Read, Some(timeout));
+                let _ = self.process();
+            }
+            return;
+        }
+        let deadline = Instant::now() + timeout;
+        let mut notices = self.notices.lock();
+        while notices.is_empty() && self.events.is_empty() && !self.stopped.load(Ordering::SeqCst) {
+            if self.callbacks_queued.wait_until(&mut notices, deadline).timed_out() {
+                break;
+            }
+        }
+    }
+
+    /// Send the queued messages and receive the ones that arrived, without blocking.
+    ///
+    /// This is what the connection thread does, for applications running their own event loop.
+    pub fn process(&self) -> Result<()> {
+        let mut lock = self.connection.lock();
+        let result = match *lock {
+            Some(ref mut conn) => self.send_and_receive(conn),
+            None => return Err(Error::ConnectionClosed),
+        };
+        drop(lock);
+
+        match result {
+            Err(Error::IoError(ref err)) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(Error::ConnectionClosed) => {
+                self.disconnect();
+                Err(Error::ConnectionClosed)
+            }
+            Err(Error::IoError(err)) => {
+                self.disconnect();
+                Err(Error::IoError(err))
+            }
+            result => result,
+        }
+    }
+
+    /// The file descriptor of the current connection, readable when a message arrived.
+    pub fn raw_fd(&self) -> Option<RawFd> {
+        self.connection.lock().as_ref().and_then(|conn| conn.raw_fd())
+    }
+
+    /// Write every message sent and received from now on to a transcript,
+    /// `None` stops recording.
+    pub fn record(&self, writer: Option<TranscriptWriter>) {
+        *self.recorder.lock() = writer;
+    }
+
+    /// Connect and perform the handshake, unless already connected.
+    pub fn connect(&self) -> Result<()> {
+        let _connecting = self.connecting.lock();
         if self.is_connected() {
             return Ok(());
         }
 
         debug!("Connecting");
 
-        let mut new_connection = SocketConnection::connect()?;
+        let client_id = self.client_id();
+        let selector = self.selector.lock().clone();
+        let transport = match selector {
+            Some(selector) => {
+                let (instance, probed) = selector.connect(client_id)?;
+                debug!("Selected {:?} instance at {}", instance.channel, instance.path.display());
+                Box::new(probed)
+            }
+            None => (self.connector)()?,
+        };
+        let mut new_connection: Box<dyn Transport> = Box::new(Metered::new(
+            Box::new(Recording::new(transport, Arc::clone(&self.recorder))),
+            Arc::clone(&self.stats),
+        ));
 
         debug!("Performing handshake");
-        new_connection.handshake(self.client_id)?;
+        let span = span!("handshake", client_id);
+        let ready = {
+            let _entered = span.enter();
+            new_connection.handshake(client_id)?
+        };
+        if ready.opcode == OpCode::Close {
+            let error = serde_json::from_str(&ready.payload).unwrap_or_default();
+            return Err(Error::HandshakeRejected(error));
+        }
         debug!("Handshake completed");
 
+        let ready = serde_json::from_str::<Payload<ReadyEvent>>(&ready.payload)
+            .map_err(|why| warn!("Failed to parse READY event: {}", why))
+            .ok()
+            .and_then(|payload| payload.data)
+            .unwrap_or_default();
+        // Whoever waits for `READY` may use the connection right away.
         *self.connection.lock() = Some(new_connection);
+        *self.ready.lock() = Some(ready.clone());
+        self.ready_changed.notify_all();
+        self.stats.lock().connected();
+        self.notify(Notice::Ready(ready));
 
         debug!("Connected");
 
         Ok(())
     }
 
-    fn disconnect(&mut self) {
+    fn disconnect(&self) {
         debug!("Disconnected");
-        self.handshake_completed = false;
-        *self.connection.lock() = None;
+        if self.connection.lock().take().is_some() {
+            self.notify(Notice::Disconnected);
+        }
+        // Responses to requests that went out are lost with the connection.
+        self.pending.lock().retain(|_, pending| {
+            if pending.sent {
+                drop(pending.reply.send(Err(Error::ConnectionClosed)));
+            }
+            !pending.sent
+        });
+        *self.ready.lock() = None;
+        self.ready_changed.notify_all();
+        self.stats.lock().disconnected();
+    }
+
+    /// Choose among several running Discord instances from the next connect on,
+    /// fails with `Error::CustomTransport` if the manager was given its own connector.
+    pub fn select_instance(&self, selector: Option<Selector>) -> Result<()> {
+        if selector.is_some() && !self.sockets {
+            return Err(Error::CustomTransport);
+        }
+        *self.selector.lock() = selector;
+        Ok(())
+    }
+
+    pub fn client_id(&self) -> u64 {
+        self.client_id.load(Ordering::SeqCst)
+    }
+
+    /// Switch to another application, reconnecting with its id and returning the new `READY` event.
+    ///
+    /// The connection thread reconnects by itself, it is waited for up to `timeout`.
+    pub fn set_client_id(&self, client_id: u64, timeout: time::Duration) -> Result<ReadyEvent> {
+        {
+            // A connection being opened right now would still use the previous id.
+            let _connecting = self.connecting.lock();
+            self.client_id.store(client_id, Ordering::SeqCst);
+            if self.is_connected() {
+                self.disconnect();
+            }
+        }
+        if self.started.load(Ordering::SeqCst) {
+            self.wake();
+            self.wait_until_ready(timeout)
+        } else {
+            self.connect()?;
+            Ok(self.ready().unwrap_or_default())
+        }
     }
 
     pub fn is_connected(&self) -> bool {
         self.connection.lock().is_some()
     }
+
+    /// The `READY` event Discord sent after the handshake of the current connection.
+    pub fn ready(&self) -> Option<ReadyEvent> {
+        self.ready.lock().clone()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats.lock().snapshot()
+    }
+
+    pub fn record_command(&self, outcome: Outcome) {
+        self.stats.lock().command(outcome);
+    }
+
+    /// Send everything queued and hand out every message that arrived, until the connection would block.
+    fn send_and_receive(&self, connection: &mut Box<dyn Transport>) -> Result<()> {
+        while let Some(msg) = self.outbound.try_pop() {
+            let nonce = nonce_of(&msg);
+            if let Err(why) = connection.send(msg) {
+                // Nobody would answer it, the caller gets the error instead.
+                let failed = match why {
+                    Error::IoError(ref err) => Error::IoError(io::Error::new(err.kind(), err.to_string())),
+                    _ => Error::ConnectionClosed,
+                };
+                self.respond(nonce, Err(failed));
+                return Err(why);
+            }
+            if let Some(nonce) = nonce {
+                if let Some(pending) = self.pending.lock().get_mut(&nonce) {
+                    pending.sent = true;
+                }
+            }
+        }
+
+        loop {
+            let msg = connection.recv()?;
+            if is_dispatch(&msg) {
+                for dropped in self.events.push(msg).into_iter().flatten() {
+                    debug!("Dropped from the event queue: {:?}", Redacted(&dropped));
+                }
+                let _notices = self.notices.lock();
+                self.callbacks_queued.notify_all();
+            } else {
+                self.respond(nonce_of(&msg), Ok(msg));
+            }
+        }
+    }
 }
 
-fn send_and_receive_loop(mut manager: Manager, retries: u32) {
+/// Keep connected and pass messages on, `first` learns how the first attempt to connect went.
+fn send_and_receive_loop(manager: &Manager, retries: u32, mut first: Option<FirstAttempt>) {
     debug!("Starting sender loop");
 
-    let mut inbound = manager.inbound.1.clone();
-    let outbound = manager.outbound.0.clone();
-
     let mut err_counter = 0;
-    while err_counter < retries {
+    #[cfg(feature = "tracing")]
+    let mut connections = 0;
+    while err_counter < retries && !manager.stopping.load(Ordering::SeqCst) {
         let connection = Arc::clone(&manager.connection);
 
         let mut lock = connection.lock();
@@ -101,26 +546,47 @@ fn send_and_receive_loop(mut manager: Manager, retries: u32) {
         match *lock {
             Some(ref mut conn) => {
                 trace!("Already connected: Sending and receiving callbacks...");
-                match send_and_receive(conn, &mut inbound, &outbound) {
+                let fd = conn.raw_fd();
+                let result = manager.send_and_receive(conn);
+                // Disconnecting takes the lock again.
+                drop(lock);
+                match result {
                     Err(Error::IoError(ref err)) if err.kind() == ErrorKind::WouldBlock => trace!("Would block: {}", err),
-                    Err(Error::ConnectionClosed) => manager.disconnect(),
+                    Err(Error::ConnectionClosed) => {
+                        manager.disconnect();
+                        continue;
+                    }
                     Err(Error::IoError(e)) => {
                         error!("Disconnecting: {}", e);
                         manager.disconnect();
+                        continue;
                     },
                     Err(why) => error!("error: {}", why),
                     _ => trace!("Everything nominal"),
                 }
 
-                drop(lock);
-                thread::sleep(time::Duration::from_millis(500));
+                // Sleep until Discord sends something or there is something to send.
+                let waited = match *manager.waker {
+                    Some(ref waker) => waker.wait(fd, None),
+                    None => poll::wait(fd, Interest::Read, Some(poll::FALLBACK_INTERVAL)),
+                };
+                if let Err(why) = waited {
+                    error!("Failed to wait for the connection: {}", why);
+                    thread::sleep(time::Duration::from_millis(500));
+                }
             }
             None => {
                 debug!("Not connected: Attempting to open connection");
                 drop(lock);
+                let span = span!("connect", attempt = err_counter + 1, retries, reconnect = connections > 0);
+                let _entered = span.enter();
                 match manager.connect() {
                     Err(err) => {
                         err_counter += 1;
+                        manager.notify(Notice::Error(ErrorEvent {
+                            code: None,
+                            message: Some(format!("Failed to connect: {}", err)),
+                        }));
                         match err {
                             Error::IoError(ref err)
                             if err.kind() == ErrorKind::ConnectionRefused =>
@@ -130,14 +596,33 @@ fn send_and_receive_loop(mut manager: Manager, retries: u32) {
                                         err_counter, retries
                                     );
                                 }
-                            why => error!(
-                                "(Try {}/{}) Failed to connect: {:?}",
+                            ref why => error!(
+                                "(Try {}/{}) Failed to connect: {}",
                                 err_counter, retries, why
                             ),
                         }
-                        thread::sleep(time::Duration::from_secs(5));
+                        if let Some(first) = first.take() {
+                            report(&first, Err(err));
+                            break;
+                        }
+                        utils::sleep(time::Duration::from_secs(5));
+                    }
+                    Ok(()) => {
+                        if let Some(first) = first.take() {
+                            if !report(&first, Ok(manager.ready().unwrap_or_default())) {
+                                debug!("Nobody waits for the connection any more");
+                                manager.disconnect();
+                                break;
+                            }
+                        }
+                        // Only give up after `retries` failed attempts in a row.
+                        err_counter = 0;
+                        #[cfg(feature = "tracing")]
+                        {
+                            connections += 1;
+                        }
+                        event!("Connected");
                     }
-                    _ => manager.handshake_completed = true,
                 }
             }
         };
@@ -147,17 +632,25 @@ fn send_and_receive_loop(mut manager: Manager, retries: u32) {
     debug!("Ending sender loop");
 }
 
-fn send_and_receive(
-    connection: &mut SocketConnection,
-    inbound: &mut Tx,
-    outbound: &Rx,
-) -> Result<()> {
-    while let Ok(msg) = outbound.try_recv() {
-        connection.send(msg).expect("Failed to send outgoing data");
+/// Hand the result of the first attempt to connect to `try_start`, false if it gave up waiting.
+fn report(first: &FirstAttempt, result: Result<ReadyEvent>) -> bool {
+    match first.lock().take() {
+        Some(sender) => sender.send(result).is_ok(),
+        None => false,
     }
+}
 
-    let msg = connection.recv()?;
-    inbound.send(msg).expect("Failed to send received data");
+/// Events pushed by Discord are sent as `DISPATCH` frames without a nonce,
+/// they must not be mistaken for the response to a pending command.
+fn is_dispatch(message: &Message) -> bool {
+    serde_json::from_str::<Value>(&message.payload)
+        .map(|payload| payload["cmd"] == "DISPATCH")
+        .unwrap_or(false)
+}
 
-    Ok(())
+/// Requests and their responses are matched by nonce, so every caller gets its own response.
+fn nonce_of(message: &Message) -> Option<String> {
+    serde_json::from_str::<Value>(&message.payload)
+        .ok()
+        .and_then(|payload| payload["nonce"].as_str().map(String::from))
 }
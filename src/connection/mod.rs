@@ -1,12 +1,21 @@
-pub use self::base::Connection;
+pub use self::base::{Connection, Transport};
+pub(crate) use self::base::{handshake_within, ping_message};
+pub use self::poll::RawFd;
+pub use self::queue::{Overflow, QueueConfig};
 pub use self::manager::Manager;
 #[cfg(unix)]
 pub use self::unix::UnixConnection as SocketConnection;
+#[cfg(unix)]
+pub use self::unix::{connect_trusted, current_uid, free_socket_path, listen, reachable_sockets};
 #[cfg(windows)]
 pub use self::windows::WindowsConnection as SocketConnection;
 
 mod base;
 mod manager;
+mod poll;
+mod queue;
+#[cfg(all(test, unix))]
+pub(crate) mod test_utils;
 #[cfg(unix)]
 mod unix;
 #[cfg(windows)]
@@ -0,0 +1,173 @@
+//! Waiting for a connection to become ready, instead of checking it on a timer.
+//!
+//! Transports that expose a file descriptor are waited on with `poll(2)`,
+//! others are checked every [`FALLBACK_INTERVAL`].
+
+use std::{io, thread, time::Duration};
+#[cfg(unix)]
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::{io::AsRawFd, net::UnixStream},
+};
+#[cfg(not(unix))]
+use std::sync::{Condvar, Mutex};
+
+#[cfg(unix)]
+use libc;
+
+#[cfg(unix)]
+pub use std::os::unix::io::RawFd;
+/// Transports can't be waited on without Unix file descriptors, this is never handed out.
+#[cfg(not(unix))]
+pub type RawFd = i32;
+
+/// How often transports without a file descriptor are checked.
+pub const FALLBACK_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interest {
+    Read,
+    Write,
+}
+
+/// Block until `fd` is ready for `interest` or `timeout` passed,
+/// without a file descriptor wait for [`FALLBACK_INTERVAL`].
+pub fn wait(fd: Option<RawFd>, interest: Interest, timeout: Option<Duration>) -> io::Result<()> {
+    match fd {
+        #[cfg(unix)]
+        Some(fd) => poll(&mut [pollfd(fd, interest)], timeout),
+        _ => {
+            thread::sleep(timeout.map_or(FALLBACK_INTERVAL, |t| t.min(FALLBACK_INTERVAL)));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(unix)]
+fn pollfd(fd: RawFd, interest: Interest) -> libc::pollfd {
+    let events = match interest {
+        Interest::Read => libc::POLLIN,
+        Interest::Write => libc::POLLOUT,
+    };
+    libc::pollfd { fd, events, revents: 0 }
+}
+
+#[cfg(unix)]
+fn poll(fds: &mut [libc::pollfd], timeout: Option<Duration>) -> io::Result<()> {
+    let timeout = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as libc::c_int);
+    match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } {
+        -1 => match io::Error::last_os_error() {
+            ref err if err.kind() == ErrorKind::Interrupted => Ok(()),
+            err => Err(err),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Interrupts a thread waiting for its connection, e.g. because there is something to send.
+pub struct Waker {
+    #[cfg(unix)]
+    sender: UnixStream,
+    #[cfg(unix)]
+    receiver: UnixStream,
+    #[cfg(not(unix))]
+    woken: Mutex<bool>,
+    #[cfg(not(unix))]
+    condvar: Condvar,
+}
+
+#[cfg(unix)]
+impl Waker {
+    pub fn new() -> io::Result<Self> {
+        let (sender, receiver) = UnixStream::pair()?;
+        sender.set_nonblocking(true)?;
+        receiver.set_nonblocking(true)?;
+        Ok(Self { sender, receiver })
+    }
+
+    pub fn wake(&self) {
+        // A full buffer means a wakeup is pending already.
+        if let Err(why) = (&self.sender).write(&[1]) {
+            if why.kind() != ErrorKind::WouldBlock {
+                error!("Failed to wake connection thread: {}", why);
+            }
+        }
+    }
+
+    /// Block until woken, `fd` is readable or `timeout` passed.
+    pub fn wait(&self, fd: Option<RawFd>, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = match fd {
+            Some(_) => timeout,
+            None => Some(timeout.map_or(FALLBACK_INTERVAL, |t| t.min(FALLBACK_INTERVAL))),
+        };
+        let mut fds = vec![pollfd(self.receiver.as_raw_fd(), Interest::Read)];
+        fds.extend(fd.map(|fd| pollfd(fd, Interest::Read)));
+        poll(&mut fds, timeout)?;
+
+        let mut buf = [0; 64];
+        loop {
+            match (&self.receiver).read(&mut buf) {
+                Ok(n) if n == buf.len() => (),
+                Ok(_) => return Ok(()),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+impl Waker {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            woken: Mutex::new(false),
+            condvar: Condvar::new(),
+        })
+    }
+
+    pub fn wake(&self) {
+        *self.woken.lock().unwrap() = true;
+        self.condvar.notify_all();
+    }
+
+    /// Block until woken or `timeout` passed, at most for [`FALLBACK_INTERVAL`].
+    pub fn wait(&self, _fd: Option<RawFd>, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.map_or(FALLBACK_INTERVAL, |t| t.min(FALLBACK_INTERVAL));
+        let woken = self.woken.lock().unwrap();
+        let (mut woken, _) = self
+            .condvar
+            .wait_timeout_while(woken, timeout, |woken| !*woken)
+            .unwrap();
+        *woken = false;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{sync::Arc, thread, time::Instant};
+
+    use super::*;
+
+    #[test]
+    fn test_waker() {
+        let waker = Arc::new(Waker::new().unwrap());
+        let (idle, _peer) = UnixStream::pair().unwrap();
+
+        let started = Instant::now();
+        waker.wait(Some(idle.as_raw_fd()), Some(Duration::from_millis(100))).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        let remote = Arc::clone(&waker);
+        let handle = thread::spawn(move || remote.wake());
+        let started = Instant::now();
+        waker.wait(Some(idle.as_raw_fd()), None).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        handle.join().unwrap();
+
+        // The wakeup was consumed, waiting again times out.
+        let started = Instant::now();
+        waker.wait(Some(idle.as_raw_fd()), Some(Duration::from_millis(50))).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use parking_lot::{Condvar, Mutex};
+use serde_json::{self, Value};
+
+use error::{Error, Result};
+use models::Message;
+
+/// What to do with a message when its queue is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Overflow {
+    /// Drop the message that waited longest.
+    DropOldest,
+    /// Only keep the newest message of every command and event with the same target,
+    /// e.g. just the last `SET_ACTIVITY` while Discord is not running, but every invite.
+    /// Drops the oldest message when that's not enough.
+    KeepNewest,
+    /// Refuse the new message, sending fails with `Error::QueueFull`.
+    Reject,
+}
+
+/// How many messages a queue holds and what happens beyond that.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl QueueConfig {
+    pub fn new(capacity: usize, overflow: Overflow) -> Self {
+        Self { capacity, overflow }
+    }
+}
+
+/// Messages waiting to be sent or to be handed out, shared by the clones of a client.
+pub(crate) struct Queue {
+    inner: Mutex<Inner>,
+    condvar: Condvar,
+}
+
+struct Inner {
+    messages: VecDeque<Message>,
+    config: QueueConfig,
+}
+
+impl Queue {
+    pub fn new(config: QueueConfig) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                messages: VecDeque::new(),
+                config,
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Change the limits, returning the messages that no longer fit.
+    pub fn configure(&self, config: QueueConfig) -> Vec<Message> {
+        let mut inner = self.inner.lock();
+        inner.config = config;
+        let excess = inner.messages.len().saturating_sub(config.capacity);
+        inner.messages.drain(..excess).collect()
+    }
+
+    /// Queue a message, returning the messages dropped to make room for it.
+    pub fn push(&self, message: Message) -> Result<Vec<Message>> {
+        let mut inner = self.inner.lock();
+        let mut dropped = Vec::new();
+        if inner.config.overflow == Overflow::KeepNewest {
+            let newest = key(&message);
+            if let Some(position) = inner.messages.iter().position(|queued| key(queued) == newest) {
+                dropped.extend(inner.messages.remove(position));
+            }
+        }
+        if inner.messages.len() >= inner.config.capacity {
+            if inner.config.overflow == Overflow::Reject {
+                return Err(Error::QueueFull);
+            }
+            let excess = inner.messages.len() + 1 - inner.config.capacity.max(1);
+            dropped.extend(inner.messages.drain(..excess));
+        }
+        inner.messages.push_back(message);
+        self.condvar.notify_one();
+        Ok(dropped)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().messages.is_empty()
+    }
+
+    pub fn try_pop(&self) -> Option<Message> {
+        self.inner.lock().messages.pop_front()
+    }
+
+    /// Wait up to `timeout` for a message, also when another clone takes the one we were woken for.
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<Message> {
+        let deadline = Instant::now() + timeout;
+        let mut inner = self.inner.lock();
+        while inner.messages.is_empty() {
+            if self.condvar.wait_until(&mut inner, deadline).timed_out() {
+                break;
+            }
+        }
+        inner.messages.pop_front()
+    }
+
+    /// Take out the messages `unwanted` is true for, e.g. a request nobody waits for any more.
+    pub fn remove<F>(&self, unwanted: F) -> Vec<Message>
+        where
+            F: Fn(&Message) -> bool,
+    {
+        let mut inner = self.inner.lock();
+        let (removed, kept): (Vec<_>, Vec<_>) = inner.messages.drain(..).partition(unwanted);
+        inner.messages = kept.into();
+        removed
+    }
+
+    pub fn drain(&self) -> Vec<Message> {
+        self.inner.lock().messages.drain(..).collect()
+    }
+}
+
+/// Messages with the same command, event and arguments apart from the activity itself
+/// replace each other with [`Overflow::KeepNewest`].
+fn key(message: &Message) -> (Value, Value, Value) {
+    let mut payload = serde_json::from_str::<Value>(&message.payload).unwrap_or_default();
+    let mut args = payload["args"].take();
+    if let Some(args) = args.as_object_mut() {
+        args.remove("activity");
+    }
+    (payload["cmd"].take(), payload["evt"].take(), args)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use models::OpCode;
+
+    use super::*;
+
+    fn frame(cmd: &str, nonce: u32) -> Message {
+        Message::new(OpCode::Frame, json![{ "cmd": cmd, "nonce": nonce }])
+    }
+
+    fn nonces(messages: Vec<Message>) -> Vec<u64> {
+        messages
+            .iter()
+            .map(|message| serde_json::from_str::<Value>(&message.payload).unwrap()["nonce"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_overflow() {
+        let queue = Queue::new(QueueConfig::new(2, Overflow::DropOldest));
+        assert!(queue.push(frame("SET_ACTIVITY", 1)).unwrap().is_empty());
+        assert!(queue.push(frame("SET_ACTIVITY", 2)).unwrap().is_empty());
+        assert_eq![nonces(queue.push(frame("SUBSCRIBE", 3)).unwrap()), vec![1]];
+        assert_eq![nonces(queue.drain()), vec![2, 3]];
+
+        let queue = Queue::new(QueueConfig::new(3, Overflow::KeepNewest));
+        queue.push(frame("SET_ACTIVITY", 1)).unwrap();
+        queue.push(frame("SUBSCRIBE", 2)).unwrap();
+        assert_eq![nonces(queue.push(frame("SET_ACTIVITY", 3)).unwrap()), vec![1]];
+        let invite = |user_id, nonce| {
+            Message::new(OpCode::Frame, json![{ "cmd": "SEND_ACTIVITY_JOIN_INVITE", "args": { "user_id": user_id }, "nonce": nonce }])
+        };
+        queue.push(invite("1", 4)).unwrap();
+        assert_eq![nonces(queue.push(invite("2", 5)).unwrap()), vec![2]];
+        assert_eq![nonces(queue.drain()), vec![3, 4, 5]];
+
+        let queue = Queue::new(QueueConfig::new(1, Overflow::Reject));
+        queue.push(frame("SET_ACTIVITY", 1)).unwrap();
+        match queue.push(frame("SET_ACTIVITY", 2)) {
+            Err(Error::QueueFull) => (),
+            other => panic!("Expected the queue to be full, got {:?}", other.map(nonces)),
+        }
+        assert_eq![nonces(queue.configure(QueueConfig::new(0, Overflow::Reject))), vec![1]];
+    }
+
+    #[test]
+    fn test_pop_timeout_waits_out_wakeups() {
+        let queue = Queue::new(QueueConfig::new(2, Overflow::DropOldest));
+        let start = Instant::now();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(20));
+                queue.condvar.notify_all();
+            });
+            assert!(queue.pop_timeout(Duration::from_millis(100)).is_none());
+        });
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
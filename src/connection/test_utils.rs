@@ -0,0 +1,31 @@
+//! Helpers for tests talking to sockets.
+
+use std::{
+    env, fs,
+    os::unix::net::UnixListener,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use models::Message;
+use utils;
+
+/// A fresh directory for the sockets of one test, `name` tells tests apart.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("discord-rpc-{}-{}", name, utils::nonce()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Answers every frame with the same payload, like a very simple Discord.
+pub fn fake_discord(path: &Path) {
+    let listener = UnixListener::bind(path).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            while let Ok(message) = Message::read_from(&mut stream) {
+                message.write_to(&mut stream).unwrap();
+            }
+        }
+    });
+}
@@ -1,8 +1,23 @@
-use std::{env, net::Shutdown, os::unix::net::UnixStream, path::PathBuf, time};
+use std::{
+    env, fs,
+    io::{self, ErrorKind},
+    mem,
+    net::Shutdown,
+    os::unix::{
+        fs::MetadataExt,
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    time,
+};
 
-use error::Result;
+use libc;
+
+use error::{Error, Result};
 
 use super::base::Connection;
+use super::poll::RawFd;
 
 pub struct UnixConnection {
     socket: UnixStream,
@@ -11,13 +26,9 @@ pub struct UnixConnection {
 impl Connection for UnixConnection {
     type Socket = UnixStream;
 
+    /// Connect to `discord-ipc-0`, making sure it belongs to the current user.
     fn connect() -> Result<Self> {
-        let connection_name = Self::socket_path(0);
-        let socket = UnixStream::connect(connection_name)?;
-        socket.set_nonblocking(true)?;
-        socket.set_write_timeout(Some(time::Duration::from_secs(30)))?;
-        socket.set_read_timeout(Some(time::Duration::from_secs(30)))?;
-        Ok(Self { socket })
+        Self::connect_to(&Self::socket_path(0))
     }
 
     fn ipc_path() -> PathBuf {
@@ -34,6 +45,37 @@ impl Connection for UnixConnection {
     fn socket(&mut self) -> &mut Self::Socket {
         &mut self.socket
     }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.socket.as_raw_fd())
+    }
+}
+
+impl UnixConnection {
+    /// Connect to the socket at `path`, e.g. one of [`Connection::socket_paths`],
+    /// making sure it belongs to the current user.
+    pub fn connect_to(path: &Path) -> Result<Self> {
+        Self::open(connect_trusted(path)?)
+    }
+
+    /// Connect to `discord-ipc-0` without checking who owns it or who listens on it.
+    ///
+    /// Only meant for setups where Discord runs as another user on purpose,
+    /// e.g. `Client::with_transport(id, || Ok(Box::new(UnixConnection::connect_unchecked()?)))`.
+    ///
+    /// Only the client built this way skips the check. Listing instances, a
+    /// [`Selector`](::instances::Selector) and [`FanOut`](::fanout::FanOut) always use
+    /// [`connect_to`](UnixConnection::connect_to) and ignore sockets of other users.
+    pub fn connect_unchecked() -> Result<Self> {
+        Self::open(UnixStream::connect(Self::socket_path(0))?)
+    }
+
+    fn open(socket: UnixStream) -> Result<Self> {
+        socket.set_nonblocking(true)?;
+        socket.set_write_timeout(Some(time::Duration::from_secs(30)))?;
+        socket.set_read_timeout(Some(time::Duration::from_secs(30)))?;
+        Ok(Self { socket })
+    }
 }
 
 impl Drop for UnixConnection {
@@ -43,3 +85,126 @@ impl Drop for UnixConnection {
             .expect("Failed to properly shut down socket");
     }
 }
+
+pub fn current_uid() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+/// Someone else's socket in a shared directory could collect our secrets and tokens.
+fn ensure_own(what: &str, uid: u32) -> Result<()> {
+    let own = current_uid();
+    if uid == own {
+        Ok(())
+    } else {
+        Err(Error::UntrustedSocket(format!("{} belongs to uid {}, not to uid {}", what, uid, own)))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(socket: &UnixStream) -> Result<Option<u32>> {
+    let mut credentials: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(Some(credentials.uid))
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+))]
+fn peer_uid(socket: &UnixStream) -> Result<Option<u32>> {
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(socket.as_raw_fd(), &mut uid, &mut gid) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(Some(uid))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly"
+)))]
+fn peer_uid(_socket: &UnixStream) -> Result<Option<u32>> {
+    Ok(None)
+}
+
+/// Connect to the socket at `path` only if both the socket file
+/// and the process listening on it belong to the current user.
+pub fn connect_trusted<P: AsRef<Path>>(path: P) -> Result<UnixStream> {
+    let path = path.as_ref();
+    ensure_own(&path.display().to_string(), fs::metadata(path)?.uid())?;
+
+    let socket = UnixStream::connect(path)?;
+    if let Some(uid) = peer_uid(&socket)? {
+        ensure_own(&format!("The process listening on {}", path.display()), uid)?;
+    }
+    Ok(socket)
+}
+
+/// Every socket path something accepts connections on.
+pub fn reachable_sockets() -> Vec<PathBuf> {
+    UnixConnection::socket_paths()
+        .into_iter()
+        .filter(|path| UnixStream::connect(path).is_ok())
+        .collect()
+}
+
+/// The first `discord-ipc-N` path nothing listens on, stale sockets count as free.
+pub fn free_socket_path() -> Result<PathBuf> {
+    UnixConnection::socket_paths()
+        .into_iter()
+        .find(|path| !path.exists() || UnixStream::connect(path).is_err())
+        .ok_or_else(|| io::Error::new(ErrorKind::AddrInUse, "Every discord-ipc socket is taken").into())
+}
+
+/// Listen on a socket at `path`, taking over a stale socket nobody listens on anymore.
+pub fn listen<P: AsRef<Path>>(path: P) -> Result<UnixListener> {
+    let path = path.as_ref();
+    if path.exists() && UnixStream::connect(path).is_err() {
+        debug!("Removing stale socket {}", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use utils;
+
+    use super::*;
+
+    #[test]
+    fn test_connect_trusted() {
+        let path = env::temp_dir().join(format!("discord-rpc-trusted-{}", utils::nonce()));
+        let _listener = UnixListener::bind(&path).unwrap();
+        assert!(connect_trusted(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+
+        match ensure_own("discord-ipc-0", current_uid() + 1) {
+            Err(Error::UntrustedSocket(why)) => assert!(why.starts_with("discord-ipc-0 belongs to uid")),
+            other => panic!("Expected an untrusted socket, got {:?}", other),
+        }
+    }
+}
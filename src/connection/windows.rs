@@ -1,4 +1,7 @@
-use std::{path::PathBuf, time};
+use std::{
+    path::{Path, PathBuf},
+    time,
+};
 
 use error::Result;
 use named_pipe::PipeClient;
@@ -13,11 +16,7 @@ impl Connection for WindowsConnection {
     type Socket = PipeClient;
 
     fn connect() -> Result<Self> {
-        let connection_name = Self::socket_path(0);
-        let mut socket = PipeClient::connect(connection_name)?;
-        socket.set_write_timeout(Some(time::Duration::from_secs(30)));
-        socket.set_read_timeout(Some(time::Duration::from_secs(30)));
-        Ok(Self { socket })
+        Self::connect_to(&Self::socket_path(0))
     }
 
     fn ipc_path() -> PathBuf {
@@ -28,3 +27,13 @@ impl Connection for WindowsConnection {
         &mut self.socket
     }
 }
+
+impl WindowsConnection {
+    /// Connect to the pipe at `path`, e.g. one of [`Connection::socket_paths`].
+    pub fn connect_to(path: &Path) -> Result<Self> {
+        let mut socket = PipeClient::connect(path)?;
+        socket.set_write_timeout(Some(time::Duration::from_secs(30)));
+        socket.set_read_timeout(Some(time::Duration::from_secs(30)));
+        Ok(Self { socket })
+    }
+}
@@ -0,0 +1,386 @@
+//! Find out why an application can't show its presence.
+//!
+//! [`diagnose`] looks at every place Discord might have put its socket, including the
+//! sandboxes of the Flatpak and Snap packages, tries a handshake where something listens
+//! and turns what it found into findings with suggested fixes.
+#![cfg(unix)]
+
+use std::{
+    env, fmt,
+    io::ErrorKind,
+    os::unix::{
+        fs::{FileTypeExt, MetadataExt},
+        net::UnixStream,
+    },
+    path::{Path, PathBuf},
+    sync::mpsc::RecvTimeoutError,
+    time::Duration,
+};
+
+use serde_json;
+
+use connection::{current_uid, Connection, SocketConnection, Transport};
+use error::{Error, Result};
+use models::{payload::Payload, Message, OpCode, ReadyEvent};
+
+/// How Discord was installed, judging by where its socket lives.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Install {
+    Native,
+    Flatpak,
+    Snap,
+}
+
+/// Everything found out about one candidate socket path.
+#[derive(Clone, Debug, Serialize)]
+pub struct SocketCheck {
+    pub path: PathBuf,
+    pub install: Install,
+    pub exists: bool,
+    pub is_socket: bool,
+    /// Permission bits, e.g. `0o755`.
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub listening: bool,
+    /// The user Discord reported after a successful handshake.
+    pub ready: Option<ReadyEvent>,
+    /// Why connecting or the handshake failed.
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Finding {
+    pub problem: String,
+    pub fix: Option<String>,
+}
+
+impl Finding {
+    fn new<P: Into<String>>(problem: P, fix: Option<String>) -> Self {
+        Self {
+            problem: problem.into(),
+            fix,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    /// The directory the client looks for sockets in.
+    pub ipc_path: PathBuf,
+    pub uid: u32,
+    /// Every candidate that exists, and `discord-ipc-0` in `ipc_path` in any case.
+    pub sockets: Vec<SocketCheck>,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Whether the client would be able to connect, as far as could be checked.
+    pub fn healthy(&self) -> bool {
+        self.sockets
+            .first()
+            .is_some_and(|socket| {
+                socket.listening && socket.error.is_none() && socket.owner == Some(self.uid)
+            })
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Looking for sockets in {} (uid {})", self.ipc_path.display(), self.uid)?;
+        for socket in &self.sockets {
+            write!(f, "  {} ", socket.path.display())?;
+            if !socket.exists {
+                writeln!(f, "missing")?;
+                continue;
+            }
+            if let (Some(mode), Some(owner)) = (socket.mode, socket.owner) {
+                write!(f, "mode {:o}, owner {}, ", mode, owner)?;
+            }
+            match (socket.listening, &socket.ready, &socket.error) {
+                (_, Some(ready), _) => {
+                    let user = ready.user.clone().unwrap_or_default();
+                    write!(
+                        f,
+                        "ready, logged in as {}#{}",
+                        user.username.unwrap_or_default(),
+                        user.discriminator.unwrap_or_default()
+                    )?;
+                }
+                (_, None, Some(error)) => write!(f, "{}", error)?,
+                (true, None, None) => write!(f, "listening")?,
+                (false, None, None) => write!(f, "nobody listening")?,
+            }
+            writeln!(f)?;
+        }
+
+        writeln!(f)?;
+        for finding in &self.findings {
+            writeln!(f, "- {}", finding.problem)?;
+            if let Some(ref fix) = finding.fix {
+                writeln!(f, "  Fix: {}", fix)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every path Discord might listen on, the ones the client tries first.
+pub fn candidate_paths() -> Vec<(Install, PathBuf)> {
+    let base = SocketConnection::ipc_path();
+    let mut dirs = vec![
+        (Install::Native, base.clone()),
+        (Install::Flatpak, base.join("app/com.discordapp.Discord")),
+        (Install::Flatpak, base.join("app/com.discordapp.DiscordCanary")),
+        (Install::Flatpak, base.join(".flatpak/com.discordapp.Discord/xdg-run")),
+        (Install::Snap, base.join("snap.discord")),
+        (Install::Snap, base.join("snap.discord-canary")),
+    ];
+    // Without XDG_RUNTIME_DIR the client looks in the temporary directory, Discord usually doesn't.
+    let runtime_dir = PathBuf::from(format!("/run/user/{}", current_uid()));
+    if runtime_dir != base {
+        dirs.push((Install::Native, runtime_dir));
+    }
+
+    dirs.into_iter()
+        .flat_map(|(install, dir)| {
+            (0..10).map(move |n| (install, dir.join(format!("discord-ipc-{}", n))))
+        })
+        .collect()
+}
+
+fn check(install: Install, path: &Path, client_id: Option<u64>, timeout: Duration) -> SocketCheck {
+    let metadata = path.metadata().ok();
+    let mut check = SocketCheck {
+        path: path.to_path_buf(),
+        install,
+        exists: metadata.is_some(),
+        is_socket: metadata.as_ref().is_some_and(|metadata| metadata.file_type().is_socket()),
+        mode: metadata.as_ref().map(|metadata| metadata.mode() & 0o7777),
+        owner: metadata.as_ref().map(|metadata| metadata.uid()),
+        listening: false,
+        ready: None,
+        error: None,
+    };
+    if !check.is_socket {
+        return check;
+    }
+
+    let stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(why) => {
+            check.error = Some(why.to_string());
+            return check;
+        }
+    };
+    check.listening = true;
+
+    if let Some(client_id) = client_id {
+        match handshake(stream, client_id, timeout) {
+            Ok(ready) => check.ready = Some(ready),
+            Err(why) => check.error = Some(why),
+        }
+    }
+    check
+}
+
+/// A blocking socket for the handshake, Discord answers quickly or not at all.
+struct Probe(UnixStream);
+
+impl Probe {
+    /// Running into the socket timeout must end the handshake instead of retrying it.
+    fn timed_out(why: Error) -> Error {
+        match why {
+            Error::IoError(ref err)
+                if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut =>
+            {
+                Error::Timeout(RecvTimeoutError::Timeout)
+            }
+            why => why,
+        }
+    }
+}
+
+impl Transport for Probe {
+    fn send(&mut self, message: Message) -> Result<()> {
+        message.write_to(&mut self.0).map_err(Probe::timed_out)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        Message::read_from(&mut self.0).map_err(Probe::timed_out)
+    }
+}
+
+fn handshake(
+    stream: UnixStream,
+    client_id: u64,
+    timeout: Duration,
+) -> ::std::result::Result<ReadyEvent, String> {
+    let timeout = Some(timeout);
+    stream
+        .set_read_timeout(timeout)
+        .and_then(|_| stream.set_write_timeout(timeout))
+        .map_err(|why| why.to_string())?;
+
+    let reply = match Probe(stream).handshake(client_id) {
+        Ok(reply) => reply,
+        Err(Error::ConnectionClosed) => return Err("Connection closed during the handshake".into()),
+        Err(why) => return Err(format!("Handshake failed: {}", why)),
+    };
+    if reply.opcode == OpCode::Close {
+        let error = serde_json::from_str(&reply.payload).unwrap_or_default();
+        return Err(Error::HandshakeRejected(error).to_string());
+    }
+    serde_json::from_str::<Payload<ReadyEvent>>(&reply.payload)
+        .ok()
+        .and_then(|payload| payload.data)
+        .ok_or_else(|| format!("Unexpected reply to the handshake: {}", reply.payload))
+}
+
+/// Check every candidate socket, handshaking with `client_id` where something listens.
+pub fn diagnose(client_id: Option<u64>) -> Report {
+    let ipc_path = SocketConnection::ipc_path();
+    let default = SocketConnection::socket_path(0);
+    let sockets: Vec<SocketCheck> = candidate_paths()
+        .into_iter()
+        .filter(|(_, path)| *path == default || path.exists())
+        .map(|(install, path)| check(install, &path, client_id, Duration::from_secs(5)))
+        .collect();
+
+    let mut report = Report {
+        ipc_path,
+        uid: current_uid(),
+        sockets,
+        findings: Vec::new(),
+    };
+    report.findings = findings(&report, client_id);
+    report
+}
+
+fn findings(report: &Report, client_id: Option<u64>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let default = &report.sockets[0];
+
+    if env::var_os("XDG_RUNTIME_DIR").is_none() {
+        findings.push(Finding::new(
+            "XDG_RUNTIME_DIR is not set, so the client looks in the temporary directory",
+            Some(format!("export XDG_RUNTIME_DIR=/run/user/{}", report.uid)),
+        ));
+    }
+
+    for socket in &report.sockets {
+        if !socket.exists {
+            continue;
+        }
+        let name = socket.path.display();
+        if !socket.is_socket {
+            findings.push(Finding::new(
+                format!("{} is not a socket", name),
+                Some(format!("rm {} and restart Discord", name)),
+            ));
+        } else if socket.owner.is_some_and(|owner| owner != report.uid) {
+            findings.push(Finding::new(
+                format!("{} belongs to uid {}, not to you", name, socket.owner.unwrap()),
+                Some("Run the application as the same user as Discord".into()),
+            ));
+        } else if socket.mode.is_some_and(|mode| mode & 0o600 != 0o600) {
+            findings.push(Finding::new(
+                format!("{} is not readable and writable by its owner", name),
+                Some(format!("chmod u+rw {}", name)),
+            ));
+        } else if !socket.listening {
+            findings.push(Finding::new(
+                format!("{} is left over from a Discord that is no longer running", name),
+                Some(format!("rm {} and restart Discord", name)),
+            ));
+        } else if let Some(ref error) = socket.error {
+            let fix = "Check that the client id belongs to an application in the developer portal";
+            findings.push(Finding::new(format!("{}: {}", name, error), Some(fix.into())));
+        }
+    }
+
+    let elsewhere = report
+        .sockets
+        .iter()
+        .skip(1)
+        .find(|socket| socket.listening);
+    match (default.listening, elsewhere) {
+        (false, Some(socket)) => {
+            let problem = if socket.install == Install::Native {
+                format!(
+                    "Discord listens on {}, but the client only connects to {}",
+                    socket.path.display(),
+                    default.path.display()
+                )
+            } else {
+                format!("Discord runs as a {:?} package, its socket is hidden in a sandbox", socket.install)
+            };
+            let fix = format!("ln -sf {} {}", socket.path.display(), default.path.display());
+            findings.push(Finding::new(problem, Some(fix)));
+        }
+        (false, None) if !report.sockets.iter().any(|socket| socket.exists) => {
+            let fix = "Start the Discord desktop app, the browser version can't show a presence";
+            findings.push(Finding::new("Discord does not seem to be running", Some(fix.into())));
+        }
+        _ => (),
+    }
+
+    if client_id.is_none() && default.listening {
+        findings.push(Finding::new(
+            "The handshake was not tried without a client id",
+            Some("Pass the client id of your application".into()),
+        ));
+    }
+    if findings.is_empty() {
+        findings.push(Finding::new("Everything looks fine", None));
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::net::UnixListener, thread};
+
+    use connection::test_utils::temp_dir;
+
+    use super::*;
+
+    #[test]
+    fn test_check_socket() {
+        let dir = temp_dir("doctor");
+
+        let missing = check(Install::Native, &dir.join("discord-ipc-0"), Some(1), Duration::from_millis(100));
+        assert!(!missing.exists && !missing.listening);
+
+        let path = dir.join("discord-ipc-1");
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            Message::read_from(&mut stream).unwrap();
+            let user = json![{ "username": "ferris" }];
+            let ready = json![{ "cmd": "DISPATCH", "evt": "READY", "data": { "v": 1, "user": user } }];
+            Message::new(OpCode::Frame, ready).write_to(&mut stream).unwrap();
+
+            let (mut stream, _) = listener.accept().unwrap();
+            Message::read_from(&mut stream).unwrap();
+            let error = json![{ "code": 4000, "message": "Invalid Client ID" }];
+            Message::new(OpCode::Close, error).write_to(&mut stream).unwrap();
+
+            let (_silent, _) = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(10));
+        });
+
+        let ready = check(Install::Native, &path, Some(1), Duration::from_millis(100));
+        assert_eq![ready.owner, Some(current_uid())];
+        assert_eq![ready.ready.unwrap().user.unwrap().username.unwrap(), "ferris"];
+
+        let rejected = check(Install::Native, &path, Some(1), Duration::from_millis(100));
+        assert!(rejected.listening && rejected.ready.is_none());
+        assert_eq![rejected.error.unwrap(), "Handshake rejected: Invalid Client ID (4000)"];
+
+        let silent = check(Install::Native, &path, Some(1), Duration::from_millis(100));
+        assert!(silent.error.unwrap().starts_with("Handshake failed"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -5,10 +5,10 @@ use std::{
     sync::mpsc::RecvTimeoutError as ChannelTimeout,
 };
 
-use crossbeam_channel::{RecvError, SendError};
+use crossbeam_channel::{RecvError, RecvTimeoutError, SendError};
 use serde_json::Error as JsonError;
 
-use models::Message;
+use models::{ErrorEvent, Message};
 
 #[derive(Debug)]
 pub enum Error {
@@ -20,11 +20,41 @@ pub enum Error {
     Conversion,
     SubscriptionFailed,
     ConnectionClosed,
+    SocketNotFound,
+    UntrustedSocket(String),
+    QueueFull,
+    HandshakeRejected(ErrorEvent),
+    InstanceNotFound,
+    CustomTransport,
+    CommandPanicked,
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        match self {
+            Error::IoError(err) => write!(f, "{}", err),
+            Error::JsonError(err) => write!(f, "{}", err),
+            Error::Timeout(err) => write!(f, "{}", err),
+            Error::SendError(err) => write!(f, "{}", err),
+            Error::RecvError(err) => write!(f, "{}", err),
+            Error::Conversion => f.write_str("Failed to convert values"),
+            Error::SubscriptionFailed => f.write_str("Failed to subscribe to event"),
+            Error::ConnectionClosed => f.write_str("Connection closed"),
+            Error::SocketNotFound => f.write_str("No Discord IPC socket found"),
+            Error::UntrustedSocket(why) => write!(f, "Refusing to use socket: {}", why),
+            Error::QueueFull => f.write_str("Dropped from a full queue"),
+            Error::HandshakeRejected(error) => write!(
+                f,
+                "Handshake rejected: {} ({})",
+                error.message.as_deref().unwrap_or_default(),
+                error.code.unwrap_or_default()
+            ),
+            Error::InstanceNotFound => f.write_str("No running Discord instance matches the selection"),
+            Error::CustomTransport => {
+                f.write_str("Instances can only be selected for clients using the Discord IPC sockets")
+            }
+            Error::CommandPanicked => f.write_str("The thread running the command panicked"),
+        }
     }
 }
 
@@ -46,4 +76,13 @@ impl From<ChannelTimeout> for Error {
     }
 }
 
+impl From<RecvTimeoutError> for Error {
+    fn from(err: RecvTimeoutError) -> Self {
+        match err {
+            RecvTimeoutError::Timeout => Error::Timeout(ChannelTimeout::Timeout),
+            RecvTimeoutError::Disconnected => Error::Timeout(ChannelTimeout::Disconnected),
+        }
+    }
+}
+
 pub type Result<T> = StdResult<T, Error>;
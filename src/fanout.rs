@@ -0,0 +1,311 @@
+//! One presence on every running Discord instance, e.g. stable and Canary logged into two accounts.
+//!
+//! [`FanOut`] connects a client to each reachable `discord-ipc-N` socket, applies commands
+//! to all of them and merges their events, each tagged with the instance it came from.
+#![cfg(feature = "rich_presence")]
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+use parking_lot::Mutex;
+use serde_json::Value;
+
+use client::Client;
+use connection::{SocketConnection, Transport};
+use error::{Error, Result};
+use instances::{self, Instance};
+use models::{payload::Payload, Activity, Event, Subscription, SubscriptionArgs};
+use providers::Providers;
+
+/// How many events are kept for [`FanOut::recv_event`], older ones are dropped.
+const EVENTS_CAPACITY: usize = 64;
+
+/// An event dispatched by one of the instances.
+#[derive(Debug, Serialize)]
+pub struct InstanceEvent {
+    pub instance: Instance,
+    pub event: Payload<Value>,
+}
+
+struct Member {
+    instance: Instance,
+    client: Client,
+}
+
+/// What every instance should show, applied again when one of them restarts.
+#[derive(Default)]
+struct Wanted {
+    presence: Option<Activity>,
+    subscriptions: Vec<(Event, SubscriptionArgs)>,
+}
+
+pub struct FanOut {
+    members: Vec<Member>,
+    wanted: Arc<Mutex<Wanted>>,
+    events: (Receiver<InstanceEvent>, Sender<InstanceEvent>),
+    stopped: Arc<AtomicBool>,
+}
+
+impl FanOut {
+    /// Connect to every instance that answers within `timeout`,
+    /// fails with `Error::SocketNotFound` if there is none.
+    ///
+    /// Instances started later are not picked up, ones that restart are reconnected.
+    pub fn connect(client_id: u64, timeout: Duration) -> Result<Self> {
+        let mut fan_out = Self::new();
+        for (instance, probed) in instances::connect_all(client_id, timeout) {
+            let path = instance.path.clone();
+            // The connection that found the instance is used first, reconnects open new ones.
+            let probed = Mutex::new(Some(probed));
+            let client = Client::with_transport(client_id, move || match probed.lock().take() {
+                Some(probed) => Ok(Box::new(probed) as Box<dyn Transport>),
+                None => Ok(Box::new(SocketConnection::connect_to(&path)?) as Box<dyn Transport>),
+            });
+            match client.try_start(u32::MAX, timeout) {
+                Ok(_) => fan_out.add(instance, client),
+                Err(why) => warn!("Skipping instance at {}: {}", instance.path.display(), why),
+            }
+        }
+        if fan_out.members.is_empty() {
+            return Err(Error::SocketNotFound);
+        }
+        Ok(fan_out)
+    }
+
+    fn new() -> Self {
+        let (sender, receiver) = bounded(EVENTS_CAPACITY);
+        Self {
+            members: Vec::new(),
+            wanted: Arc::new(Mutex::new(Wanted::default())),
+            events: (receiver, sender),
+            stopped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Take on a started client of `instance`, forwarding its events.
+    fn add(&mut self, instance: Instance, client: Client) {
+        // Only connects from now on need to be restored.
+        client.run_callbacks();
+        // The handlers must not hold on to the client, it holds on to them.
+        let reconnected = Arc::new(AtomicBool::new(false));
+        {
+            let reconnected = Arc::clone(&reconnected);
+            client.on_ready(move |_| reconnected.store(true, Ordering::SeqCst));
+        }
+        {
+            let instance = instance.clone();
+            let events = self.events.clone();
+            client.on_event(move |event| {
+                forward(&events, InstanceEvent {
+                    instance: instance.clone(),
+                    event: event.clone(),
+                })
+            });
+        }
+
+        let forwarder = Forwarder {
+            client: client.clone(),
+            wanted: Arc::clone(&self.wanted),
+            reconnected,
+            stopped: Arc::clone(&self.stopped),
+        };
+        let providers = Providers::current();
+        thread::spawn(move || {
+            providers.install();
+            forwarder.run()
+        });
+        self.members.push(Member { instance, client });
+    }
+
+    /// The instances connected to, in the order of the results of every command.
+    pub fn instances(&self) -> Vec<&Instance> {
+        self.members.iter().map(|member| &member.instance).collect()
+    }
+
+    /// Show `activity` on every instance. Instances that are not connected right now
+    /// fail with `Error::ConnectionClosed` and get the activity once they are back.
+    pub fn set_activity(&self, activity: Activity) -> Vec<Result<Payload<Activity>>> {
+        self.wanted.lock().presence = Some(activity.clone());
+        self.each(|client| client.set_activity(activity.clone()))
+    }
+
+    pub fn clear_activity(&self) -> Vec<Result<Payload<Activity>>> {
+        self.wanted.lock().presence = None;
+        self.each(|client| client.clear_activity())
+    }
+
+    pub fn subscribe(&self, evt: Event, args: SubscriptionArgs) -> Vec<Result<Payload<Subscription>>> {
+        {
+            let mut wanted = self.wanted.lock();
+            if !wanted.subscriptions.contains(&(evt, args.clone())) {
+                wanted.subscriptions.push((evt, args.clone()));
+            }
+        }
+        self.each(|client| client.subscribe(evt, args.clone()))
+    }
+
+    pub fn unsubscribe(&self, evt: Event, args: SubscriptionArgs) -> Vec<Result<Payload<Subscription>>> {
+        self.wanted
+            .lock()
+            .subscriptions
+            .retain(|subscription| *subscription != (evt, args.clone()));
+        self.each(|client| client.unsubscribe(evt, args.clone()))
+    }
+
+    /// Wait for the next event any of the instances dispatched.
+    pub fn recv_event(&self, timeout: Duration) -> Result<InstanceEvent> {
+        Ok(self.events.0.recv_timeout(timeout)?)
+    }
+
+    /// Run `command` on all connected clients at once, so one slow instance doesn't hold up the others.
+    fn each<T, F>(&self, command: F) -> Vec<Result<T>>
+        where
+            T: Send,
+            F: Fn(&Client) -> Result<T> + Sync,
+    {
+        let command = &command;
+        let providers = &Providers::current();
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .members
+                .iter()
+                .map(|member| {
+                    scope.spawn(move || {
+                        providers.clone().install();
+                        if member.client.is_connected() {
+                            command(&member.client)
+                        } else {
+                            Err(Error::ConnectionClosed)
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or(Err(Error::CommandPanicked)))
+                .collect()
+        })
+    }
+}
+
+/// Clears the presence from every instance and closes the connections.
+impl Drop for FanOut {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if self.wanted.lock().presence.is_some() {
+            for (member, result) in self.members.iter().zip(self.clear_activity()) {
+                if let Err(why) = result {
+                    debug!("Failed to clear the presence of {}: {}", member.instance.path.display(), why);
+                }
+            }
+        }
+        for member in &self.members {
+            member.client.stop();
+        }
+    }
+}
+
+/// Pass an event on, when nobody takes them keep the newest like the event queue of a client.
+fn forward(events: &(Receiver<InstanceEvent>, Sender<InstanceEvent>), mut event: InstanceEvent) {
+    while let Err(TrySendError::Full(rejected)) = events.1.try_send(event) {
+        let _ = events.0.try_recv();
+        event = rejected;
+    }
+}
+
+/// Runs the callbacks of one instance and restores what is wanted after it restarted.
+struct Forwarder {
+    client: Client,
+    wanted: Arc<Mutex<Wanted>>,
+    reconnected: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl Forwarder {
+    fn run(self) {
+        while !self.stopped.load(Ordering::SeqCst) {
+            self.client.run_callbacks_timeout(Duration::from_secs(60));
+            if self.reconnected.swap(false, Ordering::SeqCst) {
+                self.restore();
+            }
+        }
+    }
+
+    /// Discord forgets the presence and subscriptions when it restarts.
+    fn restore(&self) {
+        let (presence, subscriptions) = {
+            let wanted = self.wanted.lock();
+            (wanted.presence.clone(), wanted.subscriptions.clone())
+        };
+        for (evt, args) in subscriptions {
+            if let Err(why) = self.client.subscribe(evt, args) {
+                warn!("Failed to subscribe to {:?} again: {}", evt, why);
+            }
+        }
+        if let Some(activity) = presence {
+            if let Err(why) = self.client.set_activity(activity) {
+                warn!("Failed to restore the presence: {}", why);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Instant};
+
+    use models::ActivityBuilder;
+    use transcript::{Transcript, JOIN_SESSION};
+
+    use super::*;
+
+    #[test]
+    fn test_fan_out() {
+        let mut fan_out = FanOut::new();
+        for (n, endpoint) in ["//discord.com/api", "//canary.discord.com/api"].iter().enumerate() {
+            let ready = json![{ "config": { "api_endpoint": endpoint } }];
+            let instance = Instance {
+                path: PathBuf::from(format!("discord-ipc-{}", n)),
+                channel: None,
+                ready: serde_json::from_value(ready).unwrap(),
+            };
+            let client = Client::replay(1, Transcript::from_reader(JOIN_SESSION.as_bytes()).unwrap());
+            client.try_start(1, Duration::from_secs(5)).unwrap();
+            fan_out.add(instance, client);
+        }
+
+        let subscribed = fan_out.subscribe(Event::ActivityJoin, SubscriptionArgs::default());
+        assert!(subscribed.iter().all(Result::is_ok));
+        let mut from: Vec<_> = (0..2)
+            .map(|_| fan_out.recv_event(Duration::from_secs(5)).unwrap().instance.path)
+            .collect();
+        from.sort();
+        assert_eq![from, vec![PathBuf::from("discord-ipc-0"), PathBuf::from("discord-ipc-1")]];
+
+        let activity = ActivityBuilder::default().state("Testing".to_string()).build().unwrap();
+        let applied = fan_out.set_activity(activity.clone());
+        assert_eq![applied.len(), 2];
+        for result in applied {
+            assert_eq![result.unwrap().data, Some(activity.clone())];
+        }
+
+        // Dropping clears the presence and closes the connections.
+        let clients: Vec<_> = fan_out.members.iter().map(|member| member.client.clone()).collect();
+        drop(fan_out);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        for client in clients {
+            assert_eq![client.stats().commands.succeeded, 3];
+            while client.is_connected() {
+                assert!(Instant::now() < deadline, "The connection was not closed");
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    }
+}
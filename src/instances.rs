@@ -0,0 +1,280 @@
+//! Tell apart several Discord instances running at once, e.g. stable and Canary.
+//!
+//! Every instance listens on its own `discord-ipc-N` socket. [`list`] handshakes with each
+//! of them to learn their release [`Channel`] and logged in user, a [`Selector`] picks
+//! the one a [`Client`](::Client) connects to.
+//!
+//! Sockets are only used if they belong to the current user, see
+//! [`SocketConnection::connect_to`](::connection::SocketConnection::connect_to).
+
+use std::{
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde_json;
+
+use connection::{self, Connection, RawFd, SocketConnection, Transport};
+use error::{Error, Result};
+use models::{payload::Payload, Message, OpCode, ReadyEvent};
+
+/// How long [`Selector`]s wait for the instances to answer the handshake.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Release channel of a Discord instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Stable,
+    Ptb,
+    Canary,
+}
+
+impl Channel {
+    /// The channel serving `api_endpoint` of the `READY` configuration, e.g. `//canary.discord.com/api`.
+    pub fn from_api_endpoint(api_endpoint: &str) -> Option<Self> {
+        let host = api_endpoint
+            .trim_start_matches("https:")
+            .trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default();
+        match host {
+            "discord.com" | "discordapp.com" => Some(Channel::Stable),
+            "ptb.discord.com" | "ptb.discordapp.com" => Some(Channel::Ptb),
+            "canary.discord.com" | "canary.discordapp.com" => Some(Channel::Canary),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Channel::Stable => "stable",
+            Channel::Ptb => "ptb",
+            Channel::Canary => "canary",
+        })
+    }
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(value: &str) -> ::std::result::Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "stable" => Ok(Channel::Stable),
+            "ptb" => Ok(Channel::Ptb),
+            "canary" => Ok(Channel::Canary),
+            _ => Err(format!("unknown release channel `{}`, expected stable, ptb or canary", value)),
+        }
+    }
+}
+
+/// A Discord instance that answered the handshake.
+#[derive(Clone, Debug, Serialize)]
+pub struct Instance {
+    pub path: PathBuf,
+    pub channel: Option<Channel>,
+    pub ready: ReadyEvent,
+}
+
+impl Instance {
+    fn new(path: PathBuf, ready: ReadyEvent) -> Self {
+        let channel = ready
+            .config
+            .as_ref()
+            .and_then(|config| config.api_endpoint.as_ref())
+            .and_then(|endpoint| Channel::from_api_endpoint(endpoint));
+        Self { path, channel, ready }
+    }
+
+    pub fn user_id(&self) -> Option<&str> {
+        self.ready.user.as_ref().and_then(|user| user.id.as_deref())
+    }
+}
+
+/// Every instance that answers a handshake with `client_id` within `timeout`, in socket order.
+///
+/// The sockets are tried one after the other, connecting to a local socket fails right away
+/// where nothing listens. The time left is shared by the remaining handshakes.
+pub fn list(client_id: u64, timeout: Duration) -> Vec<Instance> {
+    connect_all(client_id, timeout)
+        .into_iter()
+        .map(|(instance, _)| instance)
+        .collect()
+}
+
+/// Like [`list`], but keep the connections to the instances open.
+pub(crate) fn connect_all(client_id: u64, timeout: Duration) -> Vec<(Instance, Probed)> {
+    connect_paths(SocketConnection::socket_paths(), client_id, timeout)
+}
+
+fn connect_paths<I>(paths: I, client_id: u64, timeout: Duration) -> Vec<(Instance, Probed)>
+    where
+        I: IntoIterator<Item = PathBuf>,
+{
+    let deadline = Instant::now() + timeout;
+    let mut found = Vec::new();
+    for path in paths {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left == Duration::from_secs(0) {
+            debug!("No time left to look for more instances");
+            break;
+        }
+        match probe(path.clone(), client_id, left) {
+            Ok(probed) => found.push(probed),
+            Err(why) => debug!("No instance at {}: {}", path.display(), why),
+        }
+    }
+    found
+}
+
+fn probe(path: PathBuf, client_id: u64, timeout: Duration) -> Result<(Instance, Probed)> {
+    let mut connection = SocketConnection::connect_to(&path)?;
+    let reply = connection::handshake_within(&mut connection, client_id, timeout)?;
+    if reply.opcode == OpCode::Close {
+        return Err(Error::HandshakeRejected(serde_json::from_str(&reply.payload).unwrap_or_default()));
+    }
+    let ready = serde_json::from_str::<Payload<ReadyEvent>>(&reply.payload)?
+        .data
+        .unwrap_or_default();
+    let probed = Probed {
+        connection,
+        ready: Some(reply),
+    };
+    Ok((Instance::new(path, ready), probed))
+}
+
+/// A connection that was handshaken with while probing, so a client can take it over
+/// without connecting again. Its next handshake is answered with the `READY` message from back then.
+pub(crate) struct Probed {
+    connection: SocketConnection,
+    ready: Option<Message>,
+}
+
+impl Transport for Probed {
+    fn send(&mut self, message: Message) -> Result<()> {
+        if message.opcode == OpCode::Handshake && self.ready.is_some() {
+            return Ok(());
+        }
+        Transport::send(&mut self.connection, message)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        match self.ready.take() {
+            Some(ready) => Ok(ready),
+            None => Transport::recv(&mut self.connection),
+        }
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Transport::raw_fd(&self.connection)
+    }
+}
+
+/// Which instance to connect to when several are running.
+///
+/// ```no_run
+/// # use discord_rpc_client::{Client, instances::{Channel, Selector}};
+/// let client = Client::new(425407036495495169);
+/// client.select_instance(Some(Selector::require().channel(Channel::Canary))).unwrap();
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selector {
+    pub channel: Option<Channel>,
+    pub user_id: Option<u64>,
+    /// Fail with `Error::InstanceNotFound` instead of falling back to the first instance.
+    pub required: bool,
+    /// Sockets never to connect to, e.g. one served by this very process.
+    pub exclude: Vec<PathBuf>,
+}
+
+impl Selector {
+    /// Connect to a matching instance if there is one, to the first one otherwise.
+    pub fn prefer() -> Self {
+        Self::default()
+    }
+
+    /// Only connect to a matching instance.
+    pub fn require() -> Self {
+        Self {
+            required: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn channel(mut self, channel: Channel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn user_id(mut self, user_id: u64) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    pub fn exclude<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.exclude.push(path.into());
+        self
+    }
+
+    pub fn matches(&self, instance: &Instance) -> bool {
+        self.channel.map_or(true, |channel| instance.channel == Some(channel))
+            && self
+                .user_id
+                .map_or(true, |user_id| instance.user_id() == Some(user_id.to_string().as_str()))
+    }
+
+    /// Pick from the running instances, handshaking with `client_id`.
+    pub fn select(&self, client_id: u64) -> Result<Instance> {
+        self.connect(client_id).map(|(instance, _)| instance)
+    }
+
+    /// Pick like [`Selector::select`], keeping the connection to the chosen instance open.
+    pub(crate) fn connect(&self, client_id: u64) -> Result<(Instance, Probed)> {
+        let paths = SocketConnection::socket_paths()
+            .into_iter()
+            .filter(|path| !self.exclude.contains(path));
+        let mut instances = connect_paths(paths, client_id, PROBE_TIMEOUT);
+        match instances.iter().position(|(instance, _)| self.matches(instance)) {
+            Some(position) => Ok(instances.swap_remove(position)),
+            None if self.required => Err(Error::InstanceNotFound),
+            None if instances.is_empty() => Err(Error::SocketNotFound),
+            None => Ok(instances.swap_remove(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selector() {
+        assert_eq![Channel::from_api_endpoint("//canary.discord.com/api"), Some(Channel::Canary)];
+        assert_eq![Channel::from_api_endpoint("https://discord.com/api"), Some(Channel::Stable)];
+        assert_eq![Channel::from_api_endpoint("//localhost:3000/api"), None];
+
+        let instance = |endpoint: &str, user_id: &str| {
+            let ready = json![{ "config": { "api_endpoint": endpoint }, "user": { "id": user_id } }];
+            Instance::new(PathBuf::new(), serde_json::from_value(ready).unwrap())
+        };
+        let stable = instance("//discord.com/api", "1");
+        let ptb = instance("//ptb.discord.com/api", "2");
+
+        assert!(Selector::prefer().matches(&stable));
+        assert!(Selector::require().channel(Channel::Ptb).matches(&ptb));
+        assert!(!Selector::require().channel(Channel::Ptb).matches(&stable));
+        assert!(Selector::require().user_id(1).matches(&stable));
+        assert!(!Selector::require().channel(Channel::Ptb).user_id(1).matches(&ptb));
+
+        let custom = ::Client::with_transport(1, || Err(Error::SocketNotFound));
+        match custom.select_instance(Some(Selector::prefer())) {
+            Err(Error::CustomTransport) => (),
+            other => panic!("Expected the selector to be rejected, got {:?}", other),
+        }
+    }
+}
@@ -3,6 +3,8 @@ extern crate bytes;
 extern crate crossbeam_channel;
 #[macro_use]
 extern crate derive_builder;
+#[cfg(unix)]
+extern crate libc;
 #[macro_use]
 extern crate log;
 #[cfg(windows)]
@@ -13,15 +15,31 @@ extern crate serde;
 extern crate serde_derive;
 #[macro_use]
 extern crate serde_json;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 extern crate uuid;
 
-pub use client::Client;
-pub use connection::{Connection, SocketConnection};
+pub use client::{Client, RpcClient};
+pub use connection::{Connection, Overflow, QueueConfig, SocketConnection, Transport};
+pub use error::{Error, Result};
 
 #[macro_use]
 mod macros;
+pub mod broker;
+mod callbacks;
 pub mod client;
 mod connection;
+pub mod doctor;
 mod error;
+pub mod fanout;
+pub mod instances;
+mod instrument;
 pub mod models;
+pub mod offline;
+pub mod providers;
+pub mod proxy;
+pub mod redact;
+pub mod relay;
+pub mod stats;
+pub mod transcript;
 mod utils;
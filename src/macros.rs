@@ -8,7 +8,7 @@ macro_rules! builder {
             $(
                 #[serde(skip_serializing_if = "Option::is_none")]
                 #[builder(default)]
-                $field: Option<$type>,
+                pub $field: Option<$type>,
             )*
         }
         impl $name {
@@ -18,3 +18,22 @@ macro_rules! builder {
         }
     };
 }
+
+/// A `tracing` span with the feature enabled, a span that does nothing otherwise.
+macro_rules! span {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = ::tracing::debug_span!($($arg)*);
+        #[cfg(not(feature = "tracing"))]
+        let span = ::instrument::Span;
+        span
+    }};
+}
+
+/// A `tracing` event with the feature enabled, nothing otherwise.
+macro_rules! event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!($($arg)*);
+    };
+}
@@ -6,7 +6,8 @@ use serde_json;
 
 use error::{Error, Result};
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OpCode {
     Handshake,
     Frame,
@@ -29,7 +30,7 @@ impl OpCode {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Message {
     pub opcode: OpCode,
     pub payload: String,
@@ -66,6 +67,29 @@ impl Message {
 
         Ok(Self { opcode, payload })
     }
+
+    /// Read exactly one frame from a stream.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut bytes = vec![0; 8];
+        match reader.read_exact(&mut bytes) {
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(Error::ConnectionClosed)
+            }
+            result => result?,
+        }
+
+        let len = (&bytes[4..]).read_u32::<LittleEndian>()? as usize;
+        bytes.resize(8 + len, 0);
+        reader.read_exact(&mut bytes[8..])?;
+
+        Self::decode(&bytes)
+    }
+
+    /// Write this message as one frame to a stream.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.encode()?)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -85,6 +109,23 @@ mod tests {
         assert_eq!(msg, decoded);
     }
 
+    #[test]
+    fn test_stream() {
+        let first = Message::new(OpCode::Frame, Something { empty: true });
+        let second = Message::new(OpCode::Ping, Something { empty: false });
+        let mut stream = vec![];
+        first.write_to(&mut stream).unwrap();
+        second.write_to(&mut stream).unwrap();
+
+        let mut reader = io::Cursor::new(stream);
+        assert_eq!(Message::read_from(&mut reader).unwrap(), first);
+        assert_eq!(Message::read_from(&mut reader).unwrap(), second);
+        match Message::read_from(&mut reader) {
+            Err(Error::ConnectionClosed) => (),
+            other => panic!("expected closed connection, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_opcode() {
         assert_eq!(OpCode::try_from(0).ok(), Some(OpCode::Handshake));
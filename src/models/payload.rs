@@ -7,7 +7,7 @@ use utils;
 
 use super::{Command, Event, Message};
 
-#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Payload<T>
     where
         T: Serialize,
@@ -50,3 +50,32 @@ impl<T> From<Message> for Payload<T>
         serde_json::from_str(&message.payload).unwrap()
     }
 }
+
+#[cfg(all(test, feature = "rich_presence"))]
+mod tests {
+    use providers;
+
+    use super::*;
+
+    #[test]
+    fn test_set_activity_snapshot() {
+        use models::rich_presence::SetActivityArgs;
+
+        providers::set_nonce_provider(|| "f2b1c3d4-0000-4000-8000-000000000000".to_string());
+        providers::set_pid_provider(providers::FixedPid(4242));
+
+        let payload = Payload::with_nonce(
+            Command::SetActivity,
+            Some(SetActivityArgs::default()),
+            None,
+            None,
+        );
+        let json = serde_json::to_string(&payload).unwrap();
+        providers::reset();
+
+        assert_eq![
+            json,
+            r#"{"cmd":"SET_ACTIVITY","args":{"pid":4242},"nonce":"f2b1c3d4-0000-4000-8000-000000000000"}"#
+        ];
+    }
+}
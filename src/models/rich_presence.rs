@@ -2,6 +2,8 @@
 
 use std::default::Default;
 
+use serde::{Deserialize, Deserializer};
+
 use utils;
 
 use super::shared::PartialUser;
@@ -89,11 +91,21 @@ pub struct Activity {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     secrets: Option<ActivitySecrets>,
-    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "deserialize_buttons"
+    )]
     #[builder(default)]
     buttons: Option<Vec<ActivityButton>>,
 }
 
+impl Activity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
 builder! {
     ActivityTimestamps {
         start: u64,
@@ -124,6 +136,33 @@ builder! {
     }
 }
 
+/// Discord only sends back the labels of buttons, so accept those as well as complete buttons.
+fn deserialize_buttons<'de, D>(deserializer: D) -> Result<Option<Vec<ActivityButton>>, D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Button {
+        Complete(ActivityButton),
+        Label(String),
+    }
+
+    let buttons: Option<Vec<Button>> = Option::deserialize(deserializer)?;
+    Ok(buttons.map(|buttons| {
+        buttons
+            .into_iter()
+            .map(|button| match button {
+                Button::Complete(button) => button,
+                Button::Label(label) => ActivityButton {
+                    label: Some(label),
+                    url: None,
+                },
+            })
+            .collect()
+    }))
+}
+
 #[derive(Builder, Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 #[builder(setter(strip_option))]
 pub struct ActivitySecrets {
@@ -144,7 +183,7 @@ mod tests {
 
     use super::*;
 
-    const FULL_JSON: &'static str = r###"{
+    const FULL_JSON: &str = r###"{
   "state": "rusting",
   "details": "detailed",
   "instance": true,
@@ -225,6 +264,66 @@ mod tests {
         assert_eq![json, FULL_JSON];
     }
 
+    fn full_activity() -> Activity {
+        let timestamps = ActivityTimestampsBuilder::default()
+            .start(1000)
+            .end(2000)
+            .build()
+            .unwrap();
+        let assets = ActivityAssetsBuilder::default()
+            .large_image("ferris".into())
+            .large_text("Ferris".into())
+            .small_image("rusting".into())
+            .small_text("Rusting...".into())
+            .build()
+            .unwrap();
+        let party = ActivityPartyBuilder::default()
+            .id(1)
+            .size((3, 6))
+            .build()
+            .unwrap();
+        let secrets = ActivitySecretsBuilder::default()
+            .join("025ed05c71f639de8bfaa0d679d7c94b2fdce12f".into())
+            .spectate("e7eb30d2ee025ed05c71ea495f770b76454ee4e0".into())
+            .game("4b2fdce12f639de8bfa7e3591b71a0d679d7c93f".into())
+            .build()
+            .unwrap();
+        let button = ActivityButtonBuilder::default()
+            .label("Click me!".into())
+            .url("https://example.com".into())
+            .build()
+            .unwrap();
+        ActivityBuilder::default()
+            .state("rusting".into())
+            .details("detailed".into())
+            .instance(true)
+            .timestamps(timestamps)
+            .assets(assets)
+            .party(party)
+            .secrets(secrets)
+            .buttons(vec![button])
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_deserialize_full_activity() {
+        let activity: Activity = serde_json::from_str(FULL_JSON).unwrap();
+
+        assert_eq![activity, full_activity()];
+    }
+
+    #[test]
+    fn test_deserialize_button_labels() {
+        let activity: Activity = serde_json::from_str(r#"{"buttons":["Click me!"]}"#).unwrap();
+        let button = ActivityButtonBuilder::default()
+            .label("Click me!".into())
+            .build()
+            .unwrap();
+
+        assert_eq![activity.buttons, Some(vec![button])];
+    }
+
     #[test]
     fn test_serialize_empty_activity() {
         let activity = Activity::new();
@@ -0,0 +1,268 @@
+#![cfg(feature = "rich_presence")]
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use parking_lot::{Condvar, Mutex};
+use serde_json::{self, Value};
+
+use client::RpcClient;
+use error::{Error, Result};
+use models::{
+    commands::{Subscription, SubscriptionArgs, SubscriptionBuilder},
+    payload::Payload,
+    rich_presence::Activity,
+    Command, Event,
+};
+use utils;
+
+/// A single presence change seen by a [`PresenceRecorder`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActivityRecord {
+    pub time: SystemTime,
+    /// The activity that was set, `None` if it was cleared.
+    pub activity: Option<Activity>,
+}
+
+struct State {
+    connected: bool,
+    history: Vec<ActivityRecord>,
+    subscriptions: Vec<Event>,
+}
+
+/// An [`RpcClient`] that never touches a socket.
+///
+/// Every activity that is set or cleared is stored with a timestamp,
+/// and events can be injected as if Discord had dispatched them.
+/// Clones share the same state, like clones of a [`Client`](crate::Client) do.
+#[derive(Clone)]
+pub struct PresenceRecorder {
+    state: Arc<Mutex<State>>,
+    connection_changed: Arc<Condvar>,
+    events: (Receiver<Payload<Value>>, Sender<Payload<Value>>),
+}
+
+impl PresenceRecorder {
+    pub fn new() -> Self {
+        let state = State {
+            connected: true,
+            history: Vec::new(),
+            subscriptions: Vec::new(),
+        };
+        let (sender, receiver) = unbounded();
+
+        Self {
+            state: Arc::new(Mutex::new(state)),
+            connection_changed: Arc::new(Condvar::new()),
+            events: (receiver, sender),
+        }
+    }
+
+    /// Simulate a lost connection, all commands fail until reconnected.
+    pub fn set_connected(&self, connected: bool) {
+        self.state.lock().connected = connected;
+        self.connection_changed.notify_all();
+    }
+
+    /// All activities set and cleared so far, oldest first.
+    pub fn history(&self) -> Vec<ActivityRecord> {
+        self.state.lock().history.clone()
+    }
+
+    /// The activity currently shown, `None` if it was never set or has been cleared.
+    pub fn current_activity(&self) -> Option<Activity> {
+        self.state
+            .lock()
+            .history
+            .last()
+            .and_then(|record| record.activity.clone())
+    }
+
+    pub fn subscriptions(&self) -> Vec<Event> {
+        self.state.lock().subscriptions.clone()
+    }
+
+    /// Queue an event, as if Discord had dispatched it.
+    pub fn inject_event(&self, evt: Event, data: Value) {
+        let payload = Payload {
+            cmd: Command::Dispatch,
+            args: None,
+            data: Some(data),
+            evt: Some(evt),
+            nonce: None,
+        };
+        self.events
+            .1
+            .send(payload)
+            .expect("Failed to queue injected event");
+    }
+
+    fn record(&self, activity: Option<Activity>) -> Result<Payload<Activity>> {
+        let mut state = self.state.lock();
+        if !state.connected {
+            return Err(Error::ConnectionClosed);
+        }
+
+        state.history.push(ActivityRecord {
+            time: utils::now(),
+            activity: activity.clone(),
+        });
+
+        Ok(Payload::with_nonce(Command::SetActivity, None, activity, None))
+    }
+
+    fn subscription(&self, cmd: Command, evt: Event) -> Result<Payload<Subscription>> {
+        let mut state = self.state.lock();
+        if !state.connected {
+            return Err(Error::ConnectionClosed);
+        }
+
+        match cmd {
+            Command::Subscribe if !state.subscriptions.contains(&evt) => {
+                state.subscriptions.push(evt)
+            }
+            Command::Unsubscribe => state.subscriptions.retain(|sub| *sub != evt),
+            _ => (),
+        }
+
+        let name = serde_json::to_value(evt)?
+            .as_str()
+            .map(String::from)
+            .ok_or(Error::Conversion)?;
+        let data = SubscriptionBuilder::default()
+            .evt(name)
+            .build()
+            .map_err(|_| Error::Conversion)?;
+
+        Ok(Payload::with_nonce(cmd, None, Some(data), Some(evt)))
+    }
+
+    fn reply(&self, cmd: Command) -> Result<Payload<Value>> {
+        if !self.state.lock().connected {
+            return Err(Error::ConnectionClosed);
+        }
+
+        Ok(Payload::with_nonce(cmd, None, None, None))
+    }
+}
+
+impl Default for PresenceRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RpcClient for PresenceRecorder {
+    fn is_connected(&self) -> bool {
+        self.state.lock().connected
+    }
+
+    fn wait_for_connection_change(&self, connected: bool, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock();
+        while state.connected == connected {
+            if self.connection_changed.wait_until(&mut state, deadline).timed_out() {
+                break;
+            }
+        }
+        state.connected
+    }
+
+    fn set_activity(&self, activity: Activity) -> Result<Payload<Activity>> {
+        self.record(Some(activity))
+    }
+
+    fn clear_activity(&self) -> Result<Payload<Activity>> {
+        self.record(None)
+    }
+
+    fn send_activity_join_invite(&self, _user_id: u64) -> Result<Payload<Value>> {
+        self.reply(Command::SendActivityJoinInvite)
+    }
+
+    fn close_activity_request(&self, _user_id: u64) -> Result<Payload<Value>> {
+        self.reply(Command::CloseActivityRequest)
+    }
+
+    fn subscribe(&self, evt: Event, _args: SubscriptionArgs) -> Result<Payload<Subscription>> {
+        self.subscription(Command::Subscribe, evt)
+    }
+
+    fn unsubscribe(&self, evt: Event, _args: SubscriptionArgs) -> Result<Payload<Subscription>> {
+        self.subscription(Command::Unsubscribe, evt)
+    }
+
+    fn recv_event(&self, timeout: Duration) -> Result<Payload<Value>> {
+        Ok(self.events.0.recv_timeout(timeout)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use models::rich_presence::ActivityBuilder;
+
+    use super::*;
+
+    fn playing(state: &str) -> Activity {
+        ActivityBuilder::default()
+            .state(state.into())
+            .build()
+            .unwrap()
+    }
+
+    fn update_presence<C: RpcClient>(client: &C, level: u32) -> Result<()> {
+        client.set_activity(playing(&format!("Level {}", level)))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_records_history() {
+        let recorder = PresenceRecorder::new();
+        let client = recorder.clone();
+
+        update_presence(&client, 1).unwrap();
+        update_presence(&client, 2).unwrap();
+        assert_eq![recorder.current_activity(), Some(playing("Level 2"))];
+
+        client.clear_activity().unwrap();
+        let history = recorder.history();
+        assert_eq![history.len(), 3];
+        assert_eq![history[0].activity, Some(playing("Level 1"))];
+        assert_eq![history[2].activity, None];
+        assert!(history[0].time <= history[2].time);
+        assert_eq![recorder.current_activity(), None];
+    }
+
+    #[test]
+    fn test_disconnected() {
+        let recorder = PresenceRecorder::new();
+        recorder.set_connected(false);
+
+        assert!(update_presence(&recorder, 1).is_err());
+        assert!(recorder.history().is_empty());
+    }
+
+    #[test]
+    fn test_subscriptions_and_events() {
+        let recorder = PresenceRecorder::new();
+        let response = recorder
+            .subscribe(Event::ActivityJoin, SubscriptionArgs::new())
+            .unwrap();
+        assert_eq![response.evt, Some(Event::ActivityJoin)];
+        assert_eq![recorder.subscriptions(), vec![Event::ActivityJoin]];
+
+        recorder.inject_event(Event::ActivityJoin, json![{ "secret": "s3cr3t" }]);
+        let event = recorder.recv_event(Duration::from_millis(10)).unwrap();
+        assert_eq![event.evt, Some(Event::ActivityJoin)];
+        assert_eq![event.data.unwrap()["secret"], "s3cr3t"];
+        assert!(recorder.recv_event(Duration::from_millis(10)).is_err());
+
+        recorder
+            .unsubscribe(Event::ActivityJoin, SubscriptionArgs::new())
+            .unwrap();
+        assert!(recorder.subscriptions().is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+//! Sources of time, nonces and process ids.
+//!
+//! By default the system clock, random UUIDs and the id of the current process are used.
+//! Tests can replace them with deterministic implementations, like [`VirtualClock`],
+//! [`SequentialNonce`] and [`FixedPid`], to get reproducible payloads and to run
+//! reconnection logic without actually waiting.
+//!
+//! The providers belong to the thread that installed them, tests running in parallel
+//! don't see each other's. The connection thread of a [`Client`](crate::Client) takes over
+//! the providers of the thread that started it.
+//!
+//! Only the delays between reconnection attempts and timestamps follow the clock,
+//! waiting for a socket or for a request to be answered always takes real time.
+
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+
+    fn sleep(&self, duration: Duration);
+}
+
+pub trait NonceProvider: Send + Sync {
+    fn nonce(&self) -> String;
+}
+
+pub trait PidProvider: Send + Sync {
+    fn pid(&self) -> u32;
+}
+
+// Allows tests to keep a handle to an installed clock, e.g. to advance a `VirtualClock`.
+impl<C> Clock for Arc<C>
+    where
+        C: Clock + ?Sized,
+{
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration)
+    }
+}
+
+impl<F> NonceProvider for F
+    where
+        F: Fn() -> String + Send + Sync,
+{
+    fn nonce(&self) -> String {
+        self()
+    }
+}
+
+impl<F> PidProvider for F
+    where
+        F: Fn() -> u32 + Send + Sync,
+{
+    fn pid(&self) -> u32 {
+        self()
+    }
+}
+
+/// The wall clock, sleeping blocks the current thread.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// A clock that only moves forward when something sleeps on it or it is advanced manually.
+pub struct VirtualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl VirtualClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(start),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new(UNIX_EPOCH)
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+        thread::yield_now();
+    }
+}
+
+/// Random version 4 UUIDs.
+pub struct RandomNonce;
+
+impl NonceProvider for RandomNonce {
+    fn nonce(&self) -> String {
+        Uuid::new_v4().to_string()
+    }
+}
+
+/// UUID shaped nonces counting up from 1.
+#[derive(Default)]
+pub struct SequentialNonce {
+    counter: AtomicUsize,
+}
+
+impl NonceProvider for SequentialNonce {
+    fn nonce(&self) -> String {
+        let n = self.counter.fetch_add(1, Ordering::SeqCst) + 1;
+        format!("00000000-0000-0000-0000-{:012}", n)
+    }
+}
+
+/// The id of the current process.
+pub struct ProcessPid;
+
+impl PidProvider for ProcessPid {
+    fn pid(&self) -> u32 {
+        std::process::id()
+    }
+}
+
+pub struct FixedPid(pub u32);
+
+impl PidProvider for FixedPid {
+    fn pid(&self) -> u32 {
+        self.0
+    }
+}
+
+/// The providers installed on a thread, `None` uses the default.
+#[derive(Clone, Default)]
+pub(crate) struct Providers {
+    clock: Option<Arc<dyn Clock>>,
+    nonce: Option<Arc<dyn NonceProvider>>,
+    pid: Option<Arc<dyn PidProvider>>,
+}
+
+impl Providers {
+    /// The providers of the current thread, to hand on to a thread it spawns.
+    pub(crate) fn current() -> Self {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Use these providers on the current thread.
+    pub(crate) fn install(self) {
+        CURRENT.with(|current| *current.borrow_mut() = self);
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Providers> = RefCell::new(Providers::default());
+}
+
+/// Use `clock` on the current thread.
+pub fn set_clock<C: Clock + 'static>(clock: C) {
+    CURRENT.with(|current| current.borrow_mut().clock = Some(Arc::new(clock)));
+}
+
+/// Use `provider` for nonces on the current thread.
+pub fn set_nonce_provider<N: NonceProvider + 'static>(provider: N) {
+    CURRENT.with(|current| current.borrow_mut().nonce = Some(Arc::new(provider)));
+}
+
+/// Use `provider` for the process id on the current thread.
+pub fn set_pid_provider<P: PidProvider + 'static>(provider: P) {
+    CURRENT.with(|current| current.borrow_mut().pid = Some(Arc::new(provider)));
+}
+
+/// Go back to the system clock, random nonces and the real process id on the current thread.
+pub fn reset() {
+    Providers::default().install();
+}
+
+// The providers are taken out before calling them, they might use providers themselves.
+
+#[allow(unused)]
+pub(crate) fn now() -> SystemTime {
+    match Providers::current().clock {
+        Some(clock) => clock.now(),
+        None => SystemClock.now(),
+    }
+}
+
+pub(crate) fn sleep(duration: Duration) {
+    match Providers::current().clock {
+        Some(clock) => clock.sleep(duration),
+        None => SystemClock.sleep(duration),
+    }
+}
+
+pub(crate) fn nonce() -> String {
+    match Providers::current().nonce {
+        Some(provider) => provider.nonce(),
+        None => RandomNonce.nonce(),
+    }
+}
+
+#[allow(unused)]
+pub(crate) fn pid() -> u32 {
+    match Providers::current().pid {
+        Some(provider) => provider.pid(),
+        None => ProcessPid.pid(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let clock = VirtualClock::default();
+        clock.sleep(Duration::from_secs(5));
+        clock.advance(Duration::from_millis(500));
+        assert_eq![clock.now(), UNIX_EPOCH + Duration::from_millis(5500)];
+    }
+
+    #[test]
+    fn test_sequential_nonce() {
+        let nonces = SequentialNonce::default();
+        assert_eq![nonces.nonce(), "00000000-0000-0000-0000-000000000001"];
+        assert_eq![nonces.nonce(), "00000000-0000-0000-0000-000000000002"];
+    }
+}
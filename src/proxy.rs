@@ -0,0 +1,269 @@
+//! A man-in-the-middle for the Discord IPC socket.
+//!
+//! The proxy listens on its own `discord-ipc-N` socket and forwards every frame
+//! to the real Discord socket, reporting each one to an observer on the way.
+#![cfg(unix)]
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use serde_json::{self, Value};
+
+use connection;
+use error::{Error, Result};
+use models::{Message, OpCode};
+use transcript::Direction;
+
+/// Called for every frame with the id of the proxied connection it belongs to.
+pub type Observer = Arc<dyn Fn(usize, Direction, &Message) + Send + Sync>;
+
+pub struct Proxy {
+    listener: UnixListener,
+    path: PathBuf,
+    upstream: PathBuf,
+    observer: Observer,
+}
+
+impl Proxy {
+    /// Listen on `path` and forward every connection to `upstream`.
+    ///
+    /// Without a `path` the first unused `discord-ipc-N` socket is taken,
+    /// without an `upstream` the first socket something listens on.
+    pub fn bind(path: Option<PathBuf>, upstream: Option<PathBuf>) -> Result<Self> {
+        let upstream = match upstream {
+            Some(upstream) => upstream,
+            None => connection::reachable_sockets()
+                .into_iter()
+                .next()
+                .ok_or(Error::SocketNotFound)?,
+        };
+        let path = match path {
+            Some(path) => path,
+            None => connection::free_socket_path()?,
+        };
+        let listener = connection::listen(&path)?;
+
+        Ok(Self {
+            listener,
+            path,
+            upstream,
+            observer: Arc::new(|_, _, _| ()),
+        })
+    }
+
+    pub fn observe<F>(mut self, observer: F) -> Self
+        where
+            F: Fn(usize, Direction, &Message) + Send + Sync + 'static,
+    {
+        self.observer = Arc::new(observer);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn upstream(&self) -> &Path {
+        &self.upstream
+    }
+
+    /// Accept and forward connections until the listener fails.
+    pub fn run(&self) -> Result<()> {
+        for (n, client) in self.listener.incoming().enumerate() {
+            self.forward(n + 1, client?);
+        }
+        Ok(())
+    }
+
+    fn forward(&self, id: usize, client: UnixStream) {
+        let upstream = match connection::connect_trusted(&self.upstream) {
+            Ok(upstream) => upstream,
+            Err(why) => {
+                error!("[{}] Failed to connect to {}: {}", id, self.upstream.display(), why);
+                return;
+            }
+        };
+        debug!("[{}] Forwarding to {}", id, self.upstream.display());
+
+        if let Err(why) = splice(id, client, upstream, &self.observer) {
+            error!("[{}] Failed to clone sockets: {}", id, why);
+        }
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        if let Err(why) = fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {}", self.path.display(), why);
+        }
+    }
+}
+
+/// A stream frames can be piped through, closing it stops the pipes in both directions.
+pub(crate) trait Stream: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+
+    fn close(&self);
+}
+
+impl Stream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+impl Stream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn close(&self) {
+        let _ = self.shutdown(Shutdown::Both);
+    }
+}
+
+/// Pipe frames between an application and Discord until either side hangs up.
+pub(crate) fn splice<A, D>(id: usize, application: A, discord: D, observer: &Observer) -> io::Result<()>
+    where
+        A: Stream,
+        D: Stream,
+{
+    let application_copy = application.try_clone()?;
+    let discord_copy = discord.try_clone()?;
+
+    let observer_copy = Arc::clone(observer);
+    thread::spawn(move || pipe(id, Direction::Sent, application, discord_copy, &observer_copy));
+    let observer = Arc::clone(observer);
+    thread::spawn(move || pipe(id, Direction::Received, discord, application_copy, &observer));
+    Ok(())
+}
+
+pub(crate) fn pipe<F, T>(id: usize, direction: Direction, mut from: F, mut to: T, observer: &Observer)
+    where
+        F: Stream,
+        T: Stream,
+{
+    loop {
+        let message = match Message::read_from(&mut from) {
+            Ok(message) => message,
+            Err(Error::ConnectionClosed) => break,
+            Err(why) => {
+                debug!("[{}] Failed to read frame: {}", id, why);
+                break;
+            }
+        };
+        observer(id, direction, &message);
+        if let Err(why) = message.write_to(&mut to) {
+            debug!("[{}] Failed to forward frame: {}", id, why);
+            break;
+        }
+    }
+
+    debug!("[{}] Closing {:?} side", id, direction);
+    // Tear down both directions, the other thread notices and stops as well.
+    from.close();
+    to.close();
+}
+
+/// Decides which frames are worth showing, an empty list matches everything.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    pub direction: Option<Direction>,
+    pub opcodes: Vec<OpCode>,
+    pub commands: Vec<String>,
+    pub events: Vec<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, direction: Direction, message: &Message) -> bool {
+        if self.direction.is_some_and(|only| only != direction) {
+            return false;
+        }
+        if !self.opcodes.is_empty() && !self.opcodes.contains(&message.opcode) {
+            return false;
+        }
+
+        let payload: Value = serde_json::from_str(&message.payload).unwrap_or(Value::Null);
+        let matches_field = |wanted: &[String], field: &str| {
+            wanted.is_empty()
+                || payload[field]
+                    .as_str()
+                    .is_some_and(|value| wanted.iter().any(|w| w.eq_ignore_ascii_case(value)))
+        };
+
+        matches_field(&self.commands, "cmd") && matches_field(&self.events, "evt")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use connection::test_utils::{fake_discord, temp_dir};
+
+    use super::*;
+
+    #[test]
+    fn test_forwards_and_observes() {
+        let dir = temp_dir("proxy");
+        let upstream = dir.join("discord-ipc-0");
+        fake_discord(&upstream);
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = parking_lot::Mutex::new(sender);
+        let proxy = Proxy::bind(Some(dir.join("discord-ipc-1")), Some(upstream))
+            .unwrap()
+            .observe(move |id, direction, message| {
+                sender.lock().send((id, direction, message.clone())).unwrap();
+            });
+        let mut client = UnixStream::connect(proxy.path()).unwrap();
+        thread::spawn(move || proxy.run());
+
+        let message = Message::new(OpCode::Frame, json![{ "cmd": "SET_ACTIVITY" }]);
+        message.write_to(&mut client).unwrap();
+        assert_eq![Message::read_from(&mut client).unwrap(), message];
+
+        let timeout = Duration::from_secs(5);
+        assert_eq![receiver.recv_timeout(timeout).unwrap(), (1, Direction::Sent, message.clone())];
+        assert_eq![receiver.recv_timeout(timeout).unwrap(), (1, Direction::Received, message)];
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_filter() {
+        let message = Message::new(OpCode::Frame, json![{ "cmd": "DISPATCH", "evt": "READY" }]);
+        assert!(Filter::default().matches(Direction::Received, &message));
+
+        let filter = Filter {
+            direction: Some(Direction::Received),
+            events: vec!["ready".into()],
+            ..Filter::default()
+        };
+        assert!(filter.matches(Direction::Received, &message));
+        assert!(!filter.matches(Direction::Sent, &message));
+
+        let filter = Filter {
+            commands: vec!["SET_ACTIVITY".into()],
+            ..Filter::default()
+        };
+        assert!(!filter.matches(Direction::Received, &message));
+
+        let filter = Filter {
+            opcodes: vec![OpCode::Ping],
+            ..Filter::default()
+        };
+        assert!(!filter.matches(Direction::Received, &message));
+    }
+}
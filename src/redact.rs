@@ -0,0 +1,129 @@
+//! Keeps secrets and tokens out of logs and transcripts.
+//!
+//! Payloads are logged and recorded with sensitive values masked, so logs and transcripts
+//! can be attached to bug reports. Call [`show_secrets`] to keep them in full while debugging locally.
+
+use std::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use serde_json::{self, Value};
+
+use models::Message;
+
+/// Fields whose string values are never logged, wherever they appear in a payload.
+pub const SENSITIVE_FIELDS: &[&str] = &[
+    "secret",
+    "secrets",
+    "access_token",
+    "refresh_token",
+    "code",
+    "token",
+];
+
+const MASK: &str = "[redacted]";
+
+static SHOW_SECRETS: AtomicBool = AtomicBool::new(false);
+
+/// Log and record payloads in full, including secrets and tokens.
+pub fn show_secrets(show: bool) {
+    SHOW_SECRETS.store(show, Ordering::SeqCst);
+}
+
+pub(crate) fn showing_secrets() -> bool {
+    SHOW_SECRETS.load(Ordering::SeqCst)
+}
+
+/// Mask the string values of sensitive fields, numbers like error codes are kept.
+pub fn redact(value: &mut Value) {
+    match *value {
+        Value::Object(ref mut object) => {
+            for (key, value) in object.iter_mut() {
+                if SENSITIVE_FIELDS.contains(&key.as_str()) {
+                    mask(value);
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(ref mut values) => values.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
+fn mask(value: &mut Value) {
+    match *value {
+        Value::String(ref mut string) => *string = MASK.to_string(),
+        Value::Object(ref mut object) => object.values_mut().for_each(mask),
+        Value::Array(ref mut values) => values.iter_mut().for_each(mask),
+        _ => (),
+    }
+}
+
+/// Compare tokens without giving away how much of a guess was right.
+pub fn same_token(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// A payload as it may be logged.
+pub fn payload(payload: &str) -> String {
+    if showing_secrets() {
+        return payload.to_string();
+    }
+    match serde_json::from_str::<Value>(payload) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        // Can't tell what is in there, better not log it at all.
+        Err(_) => format!("<{} bytes>", payload.len()),
+    }
+}
+
+/// Logs a message with its payload redacted.
+pub struct Redacted<'a>(pub &'a Message);
+
+impl<'a> fmt::Debug for Redacted<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Message")
+            .field("opcode", &self.0.opcode)
+            .field("payload", &payload(&self.0.payload))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use models::OpCode;
+
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let mut value = json![{
+            "cmd": "SET_ACTIVITY",
+            "args": { "activity": { "state": "Playing", "secrets": { "join": "j", "spectate": "s", "match": "m" } } },
+        }];
+        redact(&mut value);
+        assert_eq![value["args"]["activity"]["state"], "Playing"];
+        assert_eq![value["args"]["activity"]["secrets"], json![{ "join": MASK, "spectate": MASK, "match": MASK }]];
+
+        let mut value = json![{ "evt": "ERROR", "data": { "code": 4000, "access_token": "t" } }];
+        redact(&mut value);
+        assert_eq![value, json![{ "evt": "ERROR", "data": { "code": 4000, "access_token": MASK } }]];
+    }
+
+    #[test]
+    fn test_redacted_message() {
+        let message = Message::new(OpCode::Frame, json![{ "data": { "secret": "abc" } }]);
+        let logged = format!("{:?}", Redacted(&message));
+        assert!(!logged.contains("abc") && logged.contains(MASK));
+        assert_eq![payload("not json"), "<8 bytes>"];
+    }
+}
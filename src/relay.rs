@@ -0,0 +1,283 @@
+//! Carry the Discord IPC socket over TCP, for containers, VMs and remote sessions.
+//!
+//! The [`HostRelay`] runs next to Discord and accepts TCP connections, the [`GuestRelay`]
+//! runs where the applications are and exposes a local `discord-ipc-N` socket. Every
+//! connection to the guest is carried to the host over its own TCP connection, frame by frame.
+//!
+//! Each TCP connection starts with a frame carrying the token the host expects, if any.
+//! The token only keeps strangers out, it is not encrypted: tunnel the TCP port through SSH
+//! when the traffic crosses an untrusted network.
+#![cfg(unix)]
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use serde_json::Value;
+
+use connection;
+use error::{Error, Result};
+use models::{Message, OpCode};
+use proxy::{self, Observer, Stream};
+use redact;
+use transcript::Direction;
+
+fn hello(token: &Option<String>) -> Message {
+    Message::new(OpCode::Frame, json![{ "relay": 1, "token": token }])
+}
+
+/// Accepts relayed connections over TCP and forwards them to the Discord socket.
+pub struct HostRelay {
+    listener: TcpListener,
+    upstream: PathBuf,
+    token: Option<String>,
+    observer: Observer,
+}
+
+impl HostRelay {
+    /// Listen on `addr` and forward every connection to `upstream`,
+    /// by default the first socket something listens on.
+    pub fn bind<A: ToSocketAddrs>(addr: A, upstream: Option<PathBuf>) -> Result<Self> {
+        let upstream = match upstream {
+            Some(upstream) => upstream,
+            None => connection::reachable_sockets()
+                .into_iter()
+                .next()
+                .ok_or(Error::SocketNotFound)?,
+        };
+
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            upstream,
+            token: None,
+            observer: Arc::new(|_, _, _| ()),
+        })
+    }
+
+    /// Only accept guests presenting this token.
+    pub fn token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    pub fn observe<F>(mut self, observer: F) -> Self
+        where
+            F: Fn(usize, Direction, &Message) + Send + Sync + 'static,
+    {
+        self.observer = Arc::new(observer);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn upstream(&self) -> &Path {
+        &self.upstream
+    }
+
+    /// Accept and forward connections until the listener fails.
+    pub fn run(&self) -> Result<()> {
+        for (n, guest) in self.listener.incoming().enumerate() {
+            let guest = guest?;
+            let id = n + 1;
+            let upstream = self.upstream.clone();
+            let token = self.token.clone();
+            let observer = Arc::clone(&self.observer);
+            // The hello is read on its own thread, a silent guest must not hold up the others.
+            thread::spawn(move || {
+                if let Err(why) = accept(id, guest, &upstream, &token, &observer) {
+                    warn!("[{}] Rejected relayed connection: {}", id, why);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn accept(
+    id: usize,
+    mut guest: TcpStream,
+    upstream: &Path,
+    token: &Option<String>,
+    observer: &Observer,
+) -> Result<()> {
+    let peer = guest.peer_addr()?;
+    let hello = Message::read_from(&mut guest)?;
+    let hello: Value = serde_json::from_str(&hello.payload)?;
+    if let Some(ref expected) = *token {
+        if !hello["token"].as_str().is_some_and(|given| redact::same_token(expected, given)) {
+            guest.close();
+            let why = format!("wrong token from {}", peer);
+            return Err(io::Error::new(ErrorKind::PermissionDenied, why).into());
+        }
+    }
+
+    let discord = connection::connect_trusted(upstream)?;
+    debug!("[{}] Relaying {} to {}", id, peer, upstream.display());
+    proxy::splice(id, guest, discord, observer)?;
+    Ok(())
+}
+
+/// Exposes a local Discord socket and carries its connections to a [`HostRelay`].
+pub struct GuestRelay {
+    listener: UnixListener,
+    path: PathBuf,
+    host: String,
+    token: Option<String>,
+    observer: Observer,
+}
+
+impl GuestRelay {
+    /// Listen on `path` and relay every connection to the host relay at `host`.
+    ///
+    /// Without a `path` the first unused `discord-ipc-N` socket is taken.
+    pub fn bind<H: Into<String>>(path: Option<PathBuf>, host: H) -> Result<Self> {
+        let path = match path {
+            Some(path) => path,
+            None => connection::free_socket_path()?,
+        };
+
+        Ok(Self {
+            listener: connection::listen(&path)?,
+            path,
+            host: host.into(),
+            token: None,
+            observer: Arc::new(|_, _, _| ()),
+        })
+    }
+
+    /// Present this token to the host.
+    pub fn token(mut self, token: Option<String>) -> Self {
+        self.token = token;
+        self
+    }
+
+    pub fn observe<F>(mut self, observer: F) -> Self
+        where
+            F: Fn(usize, Direction, &Message) + Send + Sync + 'static,
+    {
+        self.observer = Arc::new(observer);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Accept and relay connections until the listener fails.
+    pub fn run(&self) -> Result<()> {
+        for (n, application) in self.listener.incoming().enumerate() {
+            self.relay(n + 1, application?);
+        }
+        Ok(())
+    }
+
+    fn relay(&self, id: usize, application: UnixStream) {
+        let connected = TcpStream::connect(&self.host)
+            .map_err(Error::from)
+            .and_then(|mut host| {
+                host.set_nodelay(true)?;
+                hello(&self.token).write_to(&mut host)?;
+                Ok(host)
+            });
+        let host = match connected {
+            Ok(host) => host,
+            Err(why) => {
+                error!("[{}] Failed to connect to {}: {}", id, self.host, why);
+                return;
+            }
+        };
+        debug!("[{}] Relaying to {}", id, self.host);
+
+        if let Err(why) = proxy::splice(id, application, host, &self.observer) {
+            error!("[{}] Failed to clone sockets: {}", id, why);
+        }
+    }
+}
+
+impl Drop for GuestRelay {
+    fn drop(&mut self) {
+        if let Err(why) = fs::remove_file(&self.path) {
+            warn!("Failed to remove {}: {}", self.path.display(), why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, time::Duration};
+
+    use parking_lot::Mutex;
+
+    use connection::test_utils::{fake_discord, temp_dir};
+
+    use super::*;
+
+    fn start(
+        dir: &Path,
+        host_token: Option<&str>,
+        guest_token: Option<&str>,
+    ) -> (PathBuf, mpsc::Receiver<Direction>) {
+        let upstream = dir.join("discord-ipc-0");
+        fake_discord(&upstream);
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let host = HostRelay::bind("127.0.0.1:0", Some(upstream))
+            .unwrap()
+            .token(host_token.map(String::from))
+            .observe(move |_, direction, _| sender.lock().send(direction).unwrap());
+        let host_addr = host.local_addr().unwrap().to_string();
+        let guest = GuestRelay::bind(Some(dir.join("discord-ipc-1")), host_addr)
+            .unwrap()
+            .token(guest_token.map(String::from));
+        let path = guest.path().to_path_buf();
+
+        thread::spawn(move || host.run());
+        thread::spawn(move || guest.run());
+        (path, receiver)
+    }
+
+    #[test]
+    fn test_relays_frames() {
+        let dir = temp_dir("relay");
+        let (path, observed) = start(&dir, Some("secret"), Some("secret"));
+
+        let mut application = UnixStream::connect(path).unwrap();
+        let message = Message::new(OpCode::Frame, json![{ "cmd": "SET_ACTIVITY" }]);
+        message.write_to(&mut application).unwrap();
+        assert_eq![Message::read_from(&mut application).unwrap(), message];
+
+        let timeout = Duration::from_secs(5);
+        assert_eq![observed.recv_timeout(timeout).unwrap(), Direction::Sent];
+        assert_eq![observed.recv_timeout(timeout).unwrap(), Direction::Received];
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_wrong_token() {
+        let dir = temp_dir("relay");
+        let (path, _) = start(&dir, Some("secret"), Some("guess"));
+
+        let mut application = UnixStream::connect(path).unwrap();
+        Message::new(OpCode::Frame, json![{}]).write_to(&mut application).unwrap();
+        match Message::read_from(&mut application) {
+            Err(Error::ConnectionClosed) => (),
+            other => panic!("Expected the connection to be closed, got {:?}", other),
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
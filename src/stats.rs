@@ -0,0 +1,254 @@
+//! Counters describing how healthy a connection to Discord is, see `Client::stats`.
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use parking_lot::Mutex;
+use serde_json::{self, Value};
+
+use connection::{RawFd, Transport};
+use error::Result;
+use models::message::Message;
+use utils;
+
+/// Upper bounds of the latency histogram buckets, in milliseconds.
+const BUCKETS: [u64; 11] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// At most this many requests wait for a response, in case responses never arrive.
+const PENDING_LIMIT: usize = 256;
+
+/// Round trip times, counted in buckets of increasing size.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    counts: [u64; BUCKETS.len() + 1],
+    sum: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        let millis = latency.as_millis() as u64;
+        let bucket = BUCKETS
+            .iter()
+            .position(|&bound| millis <= bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += latency;
+        self.max = self.max.max(latency);
+    }
+
+    /// Every bucket with its upper bound and the number of round trips that fell into it,
+    /// the last bucket has no upper bound.
+    pub fn buckets(&self) -> Vec<(Option<Duration>, u64)> {
+        BUCKETS
+            .iter()
+            .map(|&bound| Some(Duration::from_millis(bound)))
+            .chain(Some(None))
+            .zip(self.counts.iter().cloned())
+            .collect()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(self.sum / count as u32),
+        }
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            _ => Some(self.max),
+        }
+    }
+}
+
+/// Commands sent with `Client`, by how they ended.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Commands {
+    /// Discord answered and the command worked.
+    pub succeeded: u64,
+    /// Discord answered with an error.
+    pub rejected: u64,
+    /// No answer arrived, e.g. because the connection was lost.
+    pub failed: u64,
+}
+
+/// How a command ended, see [`Commands`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Outcome {
+    Succeeded,
+    Rejected,
+    Failed,
+}
+
+/// A snapshot of the counters of a client, since it was created.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    /// Bytes sent, including frame headers.
+    pub bytes_sent: u64,
+    /// Bytes received, including frame headers.
+    pub bytes_received: u64,
+    pub commands: Commands,
+    /// Connections established after the first one.
+    pub reconnects: u64,
+    /// Total time a connection was established.
+    pub connected: Duration,
+    /// Time between sending a request and receiving the response with the same nonce,
+    /// including `PING`/`PONG`.
+    pub latency: Histogram,
+}
+
+/// Collects the counters shared by the clones of a client.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    stats: Stats,
+    connections: u64,
+    connected_since: Option<SystemTime>,
+    pending: HashMap<String, SystemTime>,
+}
+
+impl Recorder {
+    pub fn snapshot(&self) -> Stats {
+        let mut stats = self.stats.clone();
+        if let Some(since) = self.connected_since {
+            stats.connected += elapsed(since);
+        }
+        stats
+    }
+
+    pub fn connected(&mut self) {
+        if self.connections > 0 {
+            self.stats.reconnects += 1;
+        }
+        self.connections += 1;
+        self.connected_since = Some(utils::now());
+    }
+
+    pub fn disconnected(&mut self) {
+        if let Some(since) = self.connected_since.take() {
+            self.stats.connected += elapsed(since);
+        }
+        // Responses to these will never arrive.
+        self.pending.clear();
+    }
+
+    pub fn command(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Succeeded => self.stats.commands.succeeded += 1,
+            Outcome::Rejected => self.stats.commands.rejected += 1,
+            Outcome::Failed => self.stats.commands.failed += 1,
+        }
+    }
+
+    fn sent(&mut self, message: &Message) {
+        self.stats.frames_sent += 1;
+        self.stats.bytes_sent += frame_len(message);
+        if let Some(nonce) = nonce(message) {
+            if self.pending.len() >= PENDING_LIMIT {
+                self.pending.clear();
+            }
+            self.pending.insert(nonce, utils::now());
+        }
+    }
+
+    fn received(&mut self, message: &Message) {
+        self.stats.frames_received += 1;
+        self.stats.bytes_received += frame_len(message);
+        if let Some(sent) = nonce(message).and_then(|nonce| self.pending.remove(&nonce)) {
+            self.stats.latency.record(elapsed(sent));
+        }
+    }
+}
+
+fn elapsed(since: SystemTime) -> Duration {
+    utils::now().duration_since(since).unwrap_or_default()
+}
+
+fn frame_len(message: &Message) -> u64 {
+    8 + message.payload.len() as u64
+}
+
+fn nonce(message: &Message) -> Option<String> {
+    serde_json::from_str::<Value>(&message.payload)
+        .ok()
+        .and_then(|payload| payload["nonce"].as_str().map(String::from))
+}
+
+/// Counts every message passing through a transport.
+pub(crate) struct Metered {
+    inner: Box<dyn Transport>,
+    recorder: Arc<Mutex<Recorder>>,
+}
+
+impl Metered {
+    pub fn new(inner: Box<dyn Transport>, recorder: Arc<Mutex<Recorder>>) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+impl Transport for Metered {
+    fn send(&mut self, message: Message) -> Result<()> {
+        self.recorder.lock().sent(&message);
+        self.inner.send(message)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        let message = self.inner.recv()?;
+        self.recorder.lock().received(&message);
+        Ok(message)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use models::message::OpCode;
+
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        assert_eq![histogram.mean(), None];
+
+        histogram.record(Duration::from_millis(3));
+        histogram.record(Duration::from_millis(7));
+        histogram.record(Duration::from_secs(60));
+        let buckets = histogram.buckets();
+        assert_eq![buckets[1], (Some(Duration::from_millis(5)), 1)];
+        assert_eq![buckets[2], (Some(Duration::from_millis(10)), 1)];
+        assert_eq![buckets[BUCKETS.len()], (None, 1)];
+        assert_eq![histogram.count(), 3];
+        assert_eq![histogram.max(), Some(Duration::from_secs(60))];
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut recorder = Recorder::default();
+        recorder.connected();
+        recorder.sent(&Message::new(OpCode::Ping, json![{ "nonce": "1" }]));
+        recorder.received(&Message::new(OpCode::Frame, json![{ "evt": "READY" }]));
+        recorder.received(&Message::new(OpCode::Pong, json![{ "nonce": "1" }]));
+        recorder.disconnected();
+        recorder.connected();
+
+        let stats = recorder.snapshot();
+        assert_eq![(stats.frames_sent, stats.frames_received), (1, 2)];
+        assert_eq![stats.bytes_sent, 8 + r#"{"nonce":"1"}"#.len() as u64];
+        assert_eq![stats.latency.count(), 1];
+        assert_eq![stats.reconnects, 1];
+    }
+}
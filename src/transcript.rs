@@ -0,0 +1,336 @@
+//! Recording and replaying of IPC sessions.
+//!
+//! A transcript is a file with one JSON object per line (NDJSON),
+//! every line describes a single message that was sent or received:
+//!
+//! ```json
+//! {"timestamp":1544054400000,"direction":"sent","opcode":"FRAME","payload":{"cmd":"SET_ACTIVITY"}}
+//! ```
+//!
+//! Use `Client::record` to write one and `Client::replay` to play it back without Discord.
+//! Secrets and tokens are masked in recorded payloads unless [`redact::show_secrets`] is on,
+//! replaying only needs the structure of the session.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+use parking_lot::Mutex;
+use serde_json::{self, Value};
+
+use connection::{RawFd, Transport};
+use error::{Error, Result};
+use models::message::{Message, OpCode};
+use redact::{self, Redacted};
+use utils;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Entry {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub direction: Direction,
+    pub opcode: OpCode,
+    pub payload: Value,
+}
+
+impl Entry {
+    pub fn new(direction: Direction, message: &Message) -> Self {
+        let timestamp = utils::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs() * 1000 + u64::from(time.subsec_millis()))
+            .unwrap_or(0);
+        // Keep payloads that aren't valid JSON as plain strings, nothing gets lost.
+        let payload = serde_json::from_str(&message.payload)
+            .unwrap_or_else(|_| Value::String(message.payload.clone()));
+
+        Self {
+            timestamp,
+            direction,
+            opcode: message.opcode,
+            payload,
+        }
+    }
+
+    pub fn to_message(&self) -> Message {
+        Message::new(self.opcode, &self.payload)
+    }
+}
+
+pub struct TranscriptWriter {
+    writer: Box<dyn Write + Send>,
+}
+
+impl TranscriptWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Self {
+            writer: Box::new(writer),
+        }
+    }
+
+    /// Append `entry`, with its payload redacted unless `redact::show_secrets` is on.
+    pub fn write(&mut self, entry: &Entry) -> Result<()> {
+        if redact::showing_secrets() {
+            serde_json::to_writer(&mut self.writer, entry)?;
+        } else {
+            let mut entry = entry.clone();
+            redact::redact(&mut entry.payload);
+            serde_json::to_writer(&mut self.writer, &entry)?;
+        }
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transcript {
+    entries: Vec<Entry>,
+}
+
+impl Transcript {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        let mut entries = Vec::new();
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(serde_json::from_str(&line)?);
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+}
+
+/// Writes everything passing through the wrapped transport to the recorder, if there is one.
+pub(crate) struct Recording {
+    inner: Box<dyn Transport>,
+    recorder: Arc<Mutex<Option<TranscriptWriter>>>,
+}
+
+impl Recording {
+    pub fn new(inner: Box<dyn Transport>, recorder: Arc<Mutex<Option<TranscriptWriter>>>) -> Self {
+        Self { inner, recorder }
+    }
+
+    fn record(&self, direction: Direction, message: &Message) {
+        if let Some(ref mut writer) = *self.recorder.lock() {
+            if let Err(why) = writer.write(&Entry::new(direction, message)) {
+                error!("Failed to record message: {}", why);
+            }
+        }
+    }
+}
+
+impl Transport for Recording {
+    fn send(&mut self, message: Message) -> Result<()> {
+        self.record(Direction::Sent, &message);
+        self.inner.send(message)
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        let message = self.inner.recv()?;
+        self.record(Direction::Received, &message);
+        Ok(message)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
+
+/// A transport that plays back the received messages of a transcript.
+///
+/// A received message is only handed out once as many messages have been sent
+/// as were sent before it in the recorded session.
+/// Nonces of replayed responses are replaced with the ones of the requests actually sent.
+/// When the transcript is exhausted the replay stays silent.
+pub struct Replay {
+    entries: Vec<Entry>,
+    position: usize,
+    sent: usize,
+    replayed_sent: usize,
+    nonces: HashMap<String, String>,
+}
+
+impl Replay {
+    pub fn new(transcript: Transcript) -> Self {
+        Self {
+            entries: transcript.entries,
+            position: 0,
+            sent: 0,
+            replayed_sent: 0,
+            nonces: HashMap::new(),
+        }
+    }
+}
+
+fn nonce_of(payload: &Value) -> Option<String> {
+    payload["nonce"].as_str().map(String::from)
+}
+
+impl Transport for Replay {
+    fn send(&mut self, message: Message) -> Result<()> {
+        debug!("Replay -> {:?}", Redacted(&message));
+
+        let recorded = self
+            .entries
+            .iter()
+            .filter(|entry| entry.direction == Direction::Sent)
+            .nth(self.sent)
+            .and_then(|entry| nonce_of(&entry.payload));
+        let actual = serde_json::from_str(&message.payload)
+            .ok()
+            .and_then(|payload| nonce_of(&payload));
+        if let (Some(recorded), Some(actual)) = (recorded, actual) {
+            self.nonces.insert(recorded, actual);
+        }
+
+        self.sent += 1;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<Message> {
+        while let Some(entry) = self.entries.get(self.position) {
+            match entry.direction {
+                Direction::Sent if self.replayed_sent < self.sent => self.replayed_sent += 1,
+                Direction::Sent => break,
+                Direction::Received => {
+                    self.position += 1;
+                    let mut entry = entry.clone();
+                    if let Some(nonce) = nonce_of(&entry.payload).and_then(|n| self.nonces.get(&n)) {
+                        entry.payload["nonce"] = Value::String(nonce.clone());
+                    }
+                    return Ok(entry.to_message());
+                }
+            }
+            self.position += 1;
+        }
+
+        Err(Error::IoError(io::Error::new(
+            ErrorKind::WouldBlock,
+            "No replayable message",
+        )))
+    }
+}
+
+/// A session subscribing to `ACTIVITY_JOIN`, receiving one, setting an activity and clearing it, for tests to replay.
+#[cfg(test)]
+pub(crate) const JOIN_SESSION: &str = r#"
+{"timestamp":1,"direction":"sent","opcode":"HANDSHAKE","payload":{"client_id":"1","v":1}}
+{"timestamp":2,"direction":"received","opcode":"FRAME","payload":{"cmd":"DISPATCH","evt":"READY","data":{"v":1}}}
+{"timestamp":3,"direction":"sent","opcode":"FRAME","payload":{"cmd":"SUBSCRIBE","evt":"ACTIVITY_JOIN","nonce":"1"}}
+{"timestamp":4,"direction":"received","opcode":"FRAME","payload":{"cmd":"SUBSCRIBE","data":{"evt":"ACTIVITY_JOIN"},"evt":"ACTIVITY_JOIN","nonce":"1"}}
+{"timestamp":5,"direction":"received","opcode":"FRAME","payload":{"cmd":"DISPATCH","evt":"ACTIVITY_JOIN","data":{"secret":"abc"}}}
+{"timestamp":6,"direction":"sent","opcode":"FRAME","payload":{"cmd":"SET_ACTIVITY","args":{"pid":1},"nonce":"2"}}
+{"timestamp":7,"direction":"received","opcode":"FRAME","payload":{"cmd":"SET_ACTIVITY","data":{"state":"Testing"},"nonce":"2"}}
+{"timestamp":8,"direction":"sent","opcode":"FRAME","payload":{"cmd":"SET_ACTIVITY","args":{"pid":1},"nonce":"3"}}
+{"timestamp":9,"direction":"received","opcode":"FRAME","payload":{"cmd":"SET_ACTIVITY","nonce":"3"}}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSCRIPT: &str = r#"
+{"timestamp":1,"direction":"sent","opcode":"HANDSHAKE","payload":{"client_id":"1","v":1}}
+{"timestamp":2,"direction":"received","opcode":"FRAME","payload":{"cmd":"DISPATCH","evt":"READY"}}
+{"timestamp":3,"direction":"sent","opcode":"FRAME","payload":{"cmd":"SET_ACTIVITY","nonce":"1"}}
+{"timestamp":4,"direction":"received","opcode":"FRAME","payload":{"cmd":"SET_ACTIVITY","nonce":"1"}}
+"#;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn would_block<T>(result: Result<T>) -> bool {
+        match result {
+            Err(Error::IoError(ref err)) => err.kind() == ErrorKind::WouldBlock,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_replay_waits_for_requests() {
+        let transcript = Transcript::from_reader(TRANSCRIPT.as_bytes()).unwrap();
+        let mut replay = Replay::new(transcript);
+
+        assert!(would_block(replay.recv()));
+        let ready = replay.handshake(1).unwrap();
+        assert_eq![ready.payload, r#"{"cmd":"DISPATCH","evt":"READY"}"#];
+        assert!(would_block(replay.recv()));
+
+        replay
+            .send(Message::new(OpCode::Frame, json![{ "nonce": "42" }]))
+            .unwrap();
+        let response = replay.recv().unwrap();
+        assert_eq![response.payload, r#"{"cmd":"SET_ACTIVITY","nonce":"42"}"#];
+        assert!(would_block(replay.recv()));
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let buffer = Buffer::default();
+        let recorder = Arc::new(Mutex::new(Some(TranscriptWriter::new(buffer.clone()))));
+        let replay = Replay::new(Transcript::from_reader(TRANSCRIPT.as_bytes()).unwrap());
+        let mut recording = Recording::new(Box::new(replay), recorder);
+
+        recording.handshake(1).unwrap();
+
+        let transcript = Transcript::from_reader(&buffer.0.lock()[..]).unwrap();
+        let entries = transcript.entries();
+        assert_eq![entries.len(), 2];
+        assert_eq![entries[0].direction, Direction::Sent];
+        assert_eq![entries[0].opcode, OpCode::Handshake];
+        assert_eq![entries[0].payload["client_id"], "1"];
+        assert_eq![entries[1].direction, Direction::Received];
+        assert_eq![entries[1].payload["evt"], "READY"];
+    }
+
+    #[test]
+    fn test_record_redacts_secrets() {
+        let buffer = Buffer::default();
+        let mut writer = TranscriptWriter::new(buffer.clone());
+        let join = Message::new(OpCode::Frame, json![{ "cmd": "DISPATCH", "evt": "ACTIVITY_JOIN", "data": { "secret": "abc" } }]);
+        writer.write(&Entry::new(Direction::Received, &join)).unwrap();
+
+        let transcript = Transcript::from_reader(&buffer.0.lock()[..]).unwrap();
+        let payload = &transcript.entries()[0].payload;
+        assert_eq![payload["evt"], "ACTIVITY_JOIN"];
+        assert_eq![payload["data"]["secret"], "[redacted]"];
+    }
+}
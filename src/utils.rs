@@ -1,10 +1,2 @@
-use uuid::Uuid;
-
 #[allow(unused)]
-pub fn pid() -> u32 {
-    std::process::id()
-}
-
-pub fn nonce() -> String {
-    Uuid::new_v4().to_string()
-}
+pub(crate) use providers::{nonce, now, pid, sleep};
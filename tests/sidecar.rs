@@ -0,0 +1,49 @@
+//! The sidecar talks NDJSON over stdout, nothing else may end up there.
+#![cfg(all(unix, feature = "cli"))]
+
+extern crate serde_json;
+
+use std::{
+    env, fs,
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use serde_json::Value;
+
+#[test]
+fn test_stdout_is_json_while_logging() {
+    // Nothing listens here, the sidecar answers without Discord.
+    let runtime_dir = env::temp_dir().join(format!("discord-rpc-sidecar-{}", std::process::id()));
+    fs::create_dir_all(&runtime_dir).unwrap();
+
+    let mut sidecar = Command::new(env!("CARGO_BIN_EXE_discord-rpc"))
+        .arg("--verbose")
+        .arg("--client-id")
+        .arg("1")
+        .arg("sidecar")
+        .env("XDG_RUNTIME_DIR", &runtime_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    sidecar
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"{\"id\": 1, \"cmd\": \"SET_ACTIVITY\", \"args\": {}}\n{\"id\": 2, \"cmd\": \"FLY\"}\n")
+        .unwrap();
+    let output = sidecar.wait_with_output().unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let replies: Vec<Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|_| panic!("Not JSON: {}", line)))
+        .collect();
+    assert_eq![replies.len(), 2];
+    assert_eq![replies[0]["id"], 1];
+    assert!(String::from_utf8_lossy(&output.stderr).contains("[DEBUG]"));
+
+    fs::remove_dir_all(runtime_dir).unwrap();
+}